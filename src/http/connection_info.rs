@@ -0,0 +1,11 @@
+use std::net::SocketAddr;
+
+//Where a request came from, set by the server for every request read from a connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConnectionInfo {
+    pub id: u64, //sequential per server, shared by every request on the connection
+    pub peer_addr: SocketAddr, //address of the client, or of the proxy in front of the server
+    pub local_addr: SocketAddr, //address the client connected to
+    pub listener_addr: SocketAddr, //address the listener was bound to, e.g. 0.0.0.0:7878
+    pub request_number: usize, //starts at 1 and grows with every request kept alive on the connection
+}
@@ -0,0 +1,225 @@
+use std::borrow::Cow;
+use std::fmt;
+
+//Media type as used in Content-Type, e.g. "text/html; charset=utf-8"
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HttpContentType {
+    main_type: Cow<'static, str>,
+    sub_type: Cow<'static, str>,
+    charset: Option<Cow<'static, str>>,
+    parameters: Vec<(String, String)>, //any parameter other than charset
+}
+
+macro_rules! content_types {
+    ($(($name:ident, $main_type:expr, $sub_type:expr, $charset:expr),)+) => {
+        impl HttpContentType {
+            $(pub const $name: HttpContentType = HttpContentType {
+                main_type: Cow::Borrowed($main_type),
+                sub_type: Cow::Borrowed($sub_type),
+                charset: $charset,
+                parameters: Vec::new(),
+            };)+
+        }
+    };
+}
+
+const UTF_8: Option<Cow<'static, str>> = Some(Cow::Borrowed("utf-8"));
+
+content_types! {
+    (TEXT_PLAIN, "text", "plain", UTF_8),
+    (TEXT_HTML, "text", "html", UTF_8),
+    (TEXT_CSS, "text", "css", UTF_8),
+    (TEXT_CSV, "text", "csv", UTF_8),
+    (TEXT_JAVASCRIPT, "text", "javascript", UTF_8),
+    (TEXT_MARKDOWN, "text", "markdown", UTF_8),
+    (TEXT_XML, "text", "xml", UTF_8),
+    (APPLICATION_JSON, "application", "json", None),
+    (APPLICATION_PROBLEM_JSON, "application", "problem+json", None),
+    (APPLICATION_OCTET_STREAM, "application", "octet-stream", None),
+    (APPLICATION_PDF, "application", "pdf", None),
+    (APPLICATION_WASM, "application", "wasm", None),
+    (APPLICATION_ZIP, "application", "zip", None),
+    (APPLICATION_GZIP, "application", "gzip", None),
+    (APPLICATION_FORM_URLENCODED, "application", "x-www-form-urlencoded", None),
+    (MULTIPART_FORM_DATA, "multipart", "form-data", None),
+    (IMAGE_PNG, "image", "png", None),
+    (IMAGE_JPEG, "image", "jpeg", None),
+    (IMAGE_GIF, "image", "gif", None),
+    (IMAGE_SVG, "image", "svg+xml", None),
+    (IMAGE_WEBP, "image", "webp", None),
+    (IMAGE_ICON, "image", "x-icon", None),
+    (FONT_WOFF, "font", "woff", None),
+    (FONT_WOFF2, "font", "woff2", None),
+    (FONT_TTF, "font", "ttf", None),
+    (AUDIO_MPEG, "audio", "mpeg", None),
+    (VIDEO_MP4, "video", "mp4", None),
+    (VIDEO_WEBM, "video", "webm", None),
+}
+
+impl HttpContentType {
+    pub fn new(main_type: &str, sub_type: &str) -> Self {
+        HttpContentType {
+            main_type: Cow::Owned(main_type.to_ascii_lowercase()),
+            sub_type: Cow::Owned(sub_type.to_ascii_lowercase()),
+            charset: None,
+            parameters: Vec::new(),
+        }
+    }
+
+    //Parses a Content-Type header value, type, subtype and parameter names are case insensitive
+    pub fn parse(value: &str) -> Option<Self> {
+        let mut parts = value.split(';');
+        let (main_type, sub_type) = parts.next()?.trim().split_once('/')?;
+        if main_type.is_empty() || sub_type.is_empty() || sub_type.contains(char::is_whitespace) {
+            return None;
+        }
+        let mut content_type = HttpContentType::new(main_type, sub_type);
+        for parameter in parts {
+            let (key, val) = parameter.trim().split_once('=')?;
+            let val = val.trim().trim_matches('"');
+            content_type = content_type.with_parameter(key.trim(), val);
+        }
+        Some(content_type)
+    }
+
+    pub fn with_charset(mut self, charset: &str) -> Self {
+        self.charset = Some(Cow::Owned(charset.to_ascii_lowercase()));
+        self
+    }
+
+    pub fn without_charset(mut self) -> Self {
+        self.charset = None;
+        self
+    }
+
+    pub fn with_parameter(mut self, key: &str, val: &str) -> Self {
+        let key = key.to_ascii_lowercase();
+        if key == "charset" {
+            return self.with_charset(val);
+        }
+        self.parameters.retain(|x| x.0 != key);
+        self.parameters.push((key, String::from(val)));
+        self
+    }
+
+    pub fn main_type(&self) -> &str {
+        &self.main_type
+    }
+
+    pub fn sub_type(&self) -> &str {
+        &self.sub_type
+    }
+
+    pub fn charset(&self) -> Option<&str> {
+        self.charset.as_deref()
+    }
+
+    pub fn parameter(&self, key: &str) -> Option<&str> {
+        if key.eq_ignore_ascii_case("charset") {
+            return self.charset();
+        }
+        self.parameters
+            .iter()
+            .find(|x| x.0.eq_ignore_ascii_case(key))
+            .map(|x| x.1.as_str())
+    }
+
+    //"type/subtype" without parameters, what should be compared to check the kind of content
+    pub fn essence(&self) -> String {
+        format!("{}/{}", self.main_type, self.sub_type)
+    }
+
+    pub fn is(&self, other: &HttpContentType) -> bool {
+        self.main_type == other.main_type && self.sub_type == other.sub_type
+    }
+
+    //Types used by FileServer when no custom mapping exists for the extension
+    pub fn from_extension(extension: &str) -> Option<Self> {
+        let content_type = match extension.to_ascii_lowercase().as_str() {
+            "txt" => HttpContentType::TEXT_PLAIN,
+            "html" | "htm" => HttpContentType::TEXT_HTML,
+            "css" => HttpContentType::TEXT_CSS,
+            "csv" => HttpContentType::TEXT_CSV,
+            "js" | "mjs" => HttpContentType::TEXT_JAVASCRIPT,
+            "md" => HttpContentType::TEXT_MARKDOWN,
+            "xml" => HttpContentType::TEXT_XML,
+            "json" | "map" => HttpContentType::APPLICATION_JSON,
+            "pdf" => HttpContentType::APPLICATION_PDF,
+            "wasm" => HttpContentType::APPLICATION_WASM,
+            "zip" => HttpContentType::APPLICATION_ZIP,
+            "gz" => HttpContentType::APPLICATION_GZIP,
+            "png" => HttpContentType::IMAGE_PNG,
+            "jpg" | "jpeg" => HttpContentType::IMAGE_JPEG,
+            "gif" => HttpContentType::IMAGE_GIF,
+            "svg" => HttpContentType::IMAGE_SVG,
+            "webp" => HttpContentType::IMAGE_WEBP,
+            "ico" => HttpContentType::IMAGE_ICON,
+            "woff" => HttpContentType::FONT_WOFF,
+            "woff2" => HttpContentType::FONT_WOFF2,
+            "ttf" => HttpContentType::FONT_TTF,
+            "mp3" => HttpContentType::AUDIO_MPEG,
+            "mp4" => HttpContentType::VIDEO_MP4,
+            "webm" => HttpContentType::VIDEO_WEBM,
+            _ => return None,
+        };
+        Some(content_type)
+    }
+}
+
+impl fmt::Display for HttpContentType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.main_type, self.sub_type)?;
+        if let Some(charset) = &self.charset {
+            write!(f, "; charset={}", charset)?;
+        }
+        for (key, val) in &self.parameters {
+            //Values with separators must be quoted, e.g. multipart boundaries
+            if val.is_empty() || val.contains(|x: char| " ;,\"=()<>@:\\/[]?{}".contains(x)) {
+                write!(f, "; {}=\"{}\"", key, val.replace('"', "\\\""))?;
+            } else {
+                write!(f, "; {}={}", key, val)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::http::HttpContentType;
+
+    #[test]
+    fn it_formats_with_charset() {
+        assert_eq!(
+            HttpContentType::TEXT_PLAIN.to_string(),
+            "text/plain; charset=utf-8"
+        );
+        assert_eq!(HttpContentType::IMAGE_PNG.to_string(), "image/png");
+        let custom = HttpContentType::new("application", "vnd.custom")
+            .with_parameter("version", "2")
+            .with_parameter("boundary", "a b");
+        assert_eq!(
+            custom.to_string(),
+            "application/vnd.custom; version=2; boundary=\"a b\""
+        );
+    }
+
+    #[test]
+    fn it_parses_header_values() {
+        let content_type =
+            HttpContentType::parse("Multipart/Form-Data; Boundary=\"xyz\"; charset=UTF-8").unwrap();
+        assert!(content_type.is(&HttpContentType::MULTIPART_FORM_DATA));
+        assert_eq!(content_type.parameter("boundary"), Some("xyz"));
+        assert_eq!(content_type.charset(), Some("utf-8"));
+        assert!(HttpContentType::parse("nonsense").is_none());
+    }
+
+    #[test]
+    fn it_maps_extensions() {
+        assert_eq!(
+            HttpContentType::from_extension("CSS"),
+            Some(HttpContentType::TEXT_CSS)
+        );
+        assert_eq!(HttpContentType::from_extension("unknown"), None);
+    }
+}
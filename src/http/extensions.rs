@@ -0,0 +1,79 @@
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+
+//Values keyed by their type, used for the server state and for what middlewares attach to a request
+#[derive(Default)]
+pub struct Extensions {
+    map: HashMap<TypeId, Box<dyn Any + Send + Sync>>,
+}
+
+impl Extensions {
+    pub fn new() -> Self {
+        Extensions::default()
+    }
+
+    //Returns the previous value of the same type
+    pub fn insert<T: Send + Sync + 'static>(&mut self, value: T) -> Option<T> {
+        self.map
+            .insert(TypeId::of::<T>(), Box::new(value))
+            .and_then(|x| x.downcast().ok())
+            .map(|x| *x)
+    }
+
+    pub fn get<T: Send + Sync + 'static>(&self) -> Option<&T> {
+        self.map
+            .get(&TypeId::of::<T>())
+            .and_then(|x| x.downcast_ref())
+    }
+
+    pub fn get_mut<T: Send + Sync + 'static>(&mut self) -> Option<&mut T> {
+        self.map
+            .get_mut(&TypeId::of::<T>())
+            .and_then(|x| x.downcast_mut())
+    }
+
+    pub fn remove<T: Send + Sync + 'static>(&mut self) -> Option<T> {
+        self.map
+            .remove(&TypeId::of::<T>())
+            .and_then(|x| x.downcast().ok())
+            .map(|x| *x)
+    }
+
+    pub fn contains<T: Send + Sync + 'static>(&self) -> bool {
+        self.map.contains_key(&TypeId::of::<T>())
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::http::extensions::Extensions;
+
+    #[derive(Debug, PartialEq)]
+    struct User(String);
+
+    #[test]
+    fn it_stores_one_value_per_type() {
+        let mut extensions = Extensions::new();
+        assert_eq!(extensions.insert(5u32), None);
+        assert_eq!(extensions.insert(User(String::from("ana"))), None);
+        assert_eq!(extensions.insert(7u32), Some(5));
+        assert_eq!(extensions.get::<u32>(), Some(&7));
+        assert_eq!(extensions.get::<u64>(), None);
+
+        extensions.get_mut::<User>().unwrap().0.push('!');
+        assert_eq!(
+            extensions.remove::<User>(),
+            Some(User(String::from("ana!")))
+        );
+        assert!(!extensions.contains::<User>());
+        assert_eq!(extensions.len(), 1);
+    }
+}
@@ -0,0 +1,404 @@
+use crate::http::header_map::{CONTENT_TYPE, COOKIE};
+use crate::http::route_param::ParamError;
+use crate::http::url_encoding::{FromUrlEncoded, UrlEncodedParams};
+use crate::http::{
+    ConnectionInfo, HeaderMap, HttpContentType, HttpError, HttpMethod, HttpRequest, HttpResponse,
+    IntoResponse,
+};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::str::FromStr;
+
+//Builds a handler argument from the request, the error is the response sent instead of calling the handler
+pub trait FromRequest: Sized {
+    fn from_request(request: &HttpRequest) -> Result<Self, HttpError>;
+}
+
+//Closures whose arguments all implement FromRequest, Args is the tuple of argument types
+pub trait Handler<Args>: Send + Sync + 'static {
+    fn call(&self, request: HttpRequest) -> HttpResponse;
+}
+
+//Turns a handler with typed arguments into a plain route handler, e.g.
+//server.get("/users/{id}", extract(|Path(id): Path<u64>, query: Query| ...))
+pub fn extract<Args, H: Handler<Args>>(
+    handler: H,
+) -> impl Fn(HttpRequest) -> HttpResponse + Send + Sync + 'static {
+    move |request| handler.call(request)
+}
+
+macro_rules! impl_handler {
+    ($($arg:ident $var:ident),+) => {
+        impl<F, R, $($arg,)+> Handler<($($arg,)+)> for F
+        where
+            F: Fn($($arg),+) -> R + Send + Sync + 'static,
+            R: IntoResponse,
+            $($arg: FromRequest,)+
+        {
+            fn call(&self, request: HttpRequest) -> HttpResponse {
+                $(
+                    let $var = match $arg::from_request(&request) {
+                        Ok(value) => value,
+                        Err(error) => return error.into_response(),
+                    };
+                )+
+                self($($var),+).into_response()
+            }
+        }
+    };
+}
+
+impl_handler!(A a);
+impl_handler!(A a, B b);
+impl_handler!(A a, B b, C c);
+impl_handler!(A a, B b, C c, D d);
+impl_handler!(A a, B b, C c, D d, E e);
+impl_handler!(A a, B b, C c, D d, E e, G g);
+impl_handler!(A a, B b, C c, D d, E e, G g, H h);
+impl_handler!(A a, B b, C c, D d, E e, G g, H h, I i);
+
+//Single route param converted with FromStr, routes with none or several params must use PathParams
+//Using it on those routes is a bug in the handler, answered with 500 and a message naming the route
+pub struct Path<T>(pub T);
+
+impl<T: FromStr> FromRequest for Path<T> {
+    fn from_request(request: &HttpRequest) -> Result<Self, HttpError> {
+        if request.route_params.len() == 1 {
+            let name = request.route_params.keys().next().unwrap();
+            return Ok(Path(request.param(name)?));
+        }
+        let mut names: Vec<&str> = request.route_params.keys().map(|x| x.as_str()).collect();
+        names.sort_unstable();
+        //Requests the router didn't dispatch have no matched route, the path is the best hint then
+        let route = request.matched_route.as_deref().unwrap_or(&request.path);
+        Err(HttpError::internal(format!(
+            "Path needs a route with exactly one param, route {} has [{}], use PathParams instead",
+            route,
+            names.join(", ")
+        )))
+    }
+}
+
+//Every route param by name, values are converted with get
+pub struct PathParams(pub HashMap<String, String>);
+
+impl PathParams {
+    pub fn get<T: FromStr>(&self, name: &str) -> Result<T, HttpError> {
+        let value = self
+            .0
+            .get(name)
+            .ok_or_else(|| ParamError::Missing(String::from(name)))?;
+        value.parse().map_err(|_| {
+            HttpError::from(ParamError::Invalid {
+                name: String::from(name),
+                value: value.clone(),
+            })
+        })
+    }
+}
+
+impl FromRequest for PathParams {
+    fn from_request(request: &HttpRequest) -> Result<Self, HttpError> {
+        Ok(PathParams(request.route_params.clone()))
+    }
+}
+
+//Query string pairs, a value that can't be converted is a 400
+pub struct Query<T = UrlEncodedParams>(pub T);
+
+impl<T: FromUrlEncoded> FromRequest for Query<T> {
+    fn from_request(request: &HttpRequest) -> Result<Self, HttpError> {
+        T::from_url_encoded(request.query_params.clone())
+            .map(Query)
+            .map_err(|message| HttpError::bad_request(&message))
+    }
+}
+
+//application/x-www-form-urlencoded body, see HttpRequest::form for the errors
+pub struct Form<T = UrlEncodedParams>(pub T);
+
+impl<T: FromUrlEncoded> FromRequest for Form<T> {
+    fn from_request(request: &HttpRequest) -> Result<Self, HttpError> {
+        request.form().map(Form)
+    }
+}
+
+pub struct Headers(pub HeaderMap);
+
+impl FromRequest for Headers {
+    fn from_request(request: &HttpRequest) -> Result<Self, HttpError> {
+        Ok(Headers(request.headers.clone()))
+    }
+}
+
+//Pairs from every Cookie header, names are case sensitive
+#[derive(Debug, Default, Clone)]
+pub struct Cookies {
+    entries: Vec<(String, String)>,
+}
+
+impl Cookies {
+    pub fn parse(headers: &HeaderMap) -> Self {
+        let entries = headers
+            .get_all(COOKIE)
+            .flat_map(|x| x.split(';'))
+            .filter_map(|pair| pair.trim().split_once('='))
+            .map(|(name, val)| {
+                let val = val.trim();
+                let unquoted = val.strip_prefix('"').and_then(|x| x.strip_suffix('"'));
+                (
+                    String::from(name.trim()),
+                    String::from(unquoted.unwrap_or(val)),
+                )
+            })
+            .collect();
+        Cookies { entries }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.entries
+            .iter()
+            .find(|x| x.0 == name)
+            .map(|x| x.1.as_str())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.entries.iter().map(|x| (x.0.as_str(), x.1.as_str()))
+    }
+}
+
+impl FromRequest for Cookies {
+    fn from_request(request: &HttpRequest) -> Result<Self, HttpError> {
+        Ok(Cookies::parse(&request.headers))
+    }
+}
+
+//Clone of the value registered with HttpServer::with_state, wrap it in an Arc if cloning is expensive
+pub struct State<T>(pub T);
+
+impl<T: Clone + Send + Sync + 'static> FromRequest for State<T> {
+    fn from_request(request: &HttpRequest) -> Result<Self, HttpError> {
+        match request.state::<T>() {
+            Some(state) => Ok(State(state.clone())),
+            None => Err(HttpError::internal(format!(
+                "No state of type {}",
+                std::any::type_name::<T>()
+            ))),
+        }
+    }
+}
+
+//Clone of a value a middleware put in the request extensions
+pub struct Extension<T>(pub T);
+
+impl<T: Clone + Send + Sync + 'static> FromRequest for Extension<T> {
+    fn from_request(request: &HttpRequest) -> Result<Self, HttpError> {
+        match request.extensions.get::<T>() {
+            Some(value) => Ok(Extension(value.clone())),
+            None => Err(HttpError::internal(format!(
+                "No extension of type {}",
+                std::any::type_name::<T>()
+            ))),
+        }
+    }
+}
+
+//Address of the client, or of the proxy in front of the server
+pub struct RemoteAddr(pub SocketAddr);
+
+impl FromRequest for RemoteAddr {
+    fn from_request(request: &HttpRequest) -> Result<Self, HttpError> {
+        request
+            .peer_addr()
+            .map(RemoteAddr)
+            .ok_or_else(|| HttpError::internal("Request without connection"))
+    }
+}
+
+impl FromRequest for ConnectionInfo {
+    fn from_request(request: &HttpRequest) -> Result<Self, HttpError> {
+        request
+            .connection
+            .ok_or_else(|| HttpError::internal("Request without connection"))
+    }
+}
+
+impl FromRequest for HttpMethod {
+    fn from_request(request: &HttpRequest) -> Result<Self, HttpError> {
+        Ok(request.method.clone())
+    }
+}
+
+//Raw body, empty when the request had none
+impl FromRequest for Vec<u8> {
+    fn from_request(request: &HttpRequest) -> Result<Self, HttpError> {
+        Ok(request.content.clone().unwrap_or_default())
+    }
+}
+
+impl FromRequest for String {
+    fn from_request(request: &HttpRequest) -> Result<Self, HttpError> {
+        String::from_utf8(request.content.clone().unwrap_or_default())
+            .map_err(|_| HttpError::bad_request("Body is not valid UTF-8"))
+    }
+}
+
+//None instead of rejecting the request, e.g. for optional cookies or bodies
+impl<T: FromRequest> FromRequest for Option<T> {
+    fn from_request(request: &HttpRequest) -> Result<Self, HttpError> {
+        Ok(T::from_request(request).ok())
+    }
+}
+
+//Lets the handler decide what to do with the error
+impl<T: FromRequest> FromRequest for Result<T, HttpError> {
+    fn from_request(request: &HttpRequest) -> Result<Self, HttpError> {
+        Ok(T::from_request(request))
+    }
+}
+
+//415 unless the request Content-Type has the same type and subtype, parameters are ignored
+pub fn require_content_type(
+    request: &HttpRequest,
+    expected: &HttpContentType,
+) -> Result<(), HttpError> {
+    let content_type = request
+        .headers
+        .get(CONTENT_TYPE)
+        .and_then(HttpContentType::parse);
+    match content_type {
+        Some(content_type) if content_type.is(expected) => Ok(()),
+        _ => Err(HttpError::unsupported_media_type(&format!(
+            "Expected Content-Type {}",
+            expected.essence()
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::http::extract::{
+        extract, Cookies, Extension, Form, FromRequest, Path, PathParams, Query, State,
+    };
+    use crate::http::header_map::{CONTENT_TYPE, COOKIE};
+    use crate::http::Extensions;
+    use crate::http::{HttpError, HttpMethod, HttpRequest, StatusCode};
+    use std::sync::Arc;
+
+    fn request_with_param(name: &str, value: &str) -> HttpRequest {
+        let mut request = HttpRequest::new(HttpMethod::GET, String::from("/users?sort=name"));
+        request
+            .route_params
+            .insert(String::from(name), String::from(value));
+        request
+    }
+
+    #[test]
+    fn it_calls_handlers_with_extracted_args() {
+        let handler = extract(|Path(id): Path<u64>, Query(query): Query| {
+            format!("{} {}", id, query.get("sort").unwrap())
+        });
+        let response = handler(request_with_param("id", "42"));
+        assert_eq!(response.content_as_string(), "42 name");
+
+        let response = handler(request_with_param("id", "abc"));
+        assert_eq!(response.status_code, StatusCode::_400);
+        assert!(response.error.is_some());
+    }
+
+    #[test]
+    fn it_names_the_route_when_path_has_several_params() {
+        let mut request = HttpRequest::new(HttpMethod::GET, String::from("/users/1/posts/2"));
+        request
+            .route_params
+            .insert(String::from("id"), String::from("1"));
+        request
+            .route_params
+            .insert(String::from("post"), String::from("2"));
+        request.matched_route = Some(String::from("/users/{id}/posts/{post}"));
+        let error = match Path::<u64>::from_request(&request) {
+            Err(error) => error,
+            Ok(_) => panic!("Path should need exactly one param"),
+        };
+        assert_eq!(error.status_code(), StatusCode::_500);
+        let cause = error.cause().unwrap().to_string();
+        assert!(cause.contains("route /users/{id}/posts/{post} has [id, post]"));
+    }
+
+    #[test]
+    fn it_gets_params_by_name() {
+        let handler = extract(|params: PathParams| -> Result<String, HttpError> {
+            Ok(format!("{}", params.get::<i32>("id")? + 1))
+        });
+        let response = handler(request_with_param("id", "-2"));
+        assert_eq!(response.content_as_string(), "-1");
+    }
+
+    #[test]
+    fn it_checks_form_content_type() {
+        let handler = extract(|Form(form): Form| String::from(form.get("name").unwrap()));
+        let mut request = HttpRequest::new(HttpMethod::POST, String::from("/"));
+        request.content = Some(b"name=Ana+Maria".to_vec());
+        let response = handler(request);
+        assert_eq!(response.status_code, StatusCode::_415);
+
+        let mut request = HttpRequest::new(HttpMethod::POST, String::from("/"));
+        request.headers.insert(
+            CONTENT_TYPE,
+            "application/x-www-form-urlencoded; charset=UTF-8",
+        );
+        request.content = Some(b"name=Ana+Maria".to_vec());
+        let response = handler(request);
+        assert_eq!(response.content_as_string(), "Ana Maria");
+    }
+
+    #[test]
+    fn it_parses_cookies() {
+        let mut request = HttpRequest::new(HttpMethod::GET, String::from("/"));
+        request
+            .headers
+            .append(COOKIE, "session=abc; theme=\"dark\"");
+        request.headers.append(COOKIE, "lang=pt");
+        let cookies = Cookies::parse(&request.headers);
+        assert_eq!(cookies.get("session"), Some("abc"));
+        assert_eq!(cookies.get("theme"), Some("dark"));
+        assert_eq!(cookies.get("lang"), Some("pt"));
+        assert_eq!(cookies.get("Session"), None);
+    }
+
+    #[test]
+    fn it_allows_optional_extractors() {
+        let handler = extract(|id: Option<Path<u8>>, method: HttpMethod| match id {
+            Some(Path(id)) => format!("{} {}", method.to_string(), id),
+            None => String::from("none"),
+        });
+        let response = handler(request_with_param("id", "300"));
+        assert_eq!(response.content_as_string(), "none");
+    }
+
+    #[test]
+    fn it_extracts_state_and_extensions() {
+        #[derive(Clone)]
+        struct User(&'static str);
+
+        let handler = extract(
+            |State(greeting): State<String>, Extension(user): Extension<User>| {
+                format!("{} {}", greeting, user.0)
+            },
+        );
+        let mut state = Extensions::new();
+        state.insert(String::from("Hello"));
+        let mut request = HttpRequest::new(HttpMethod::GET, String::from("/"));
+        request.state = Arc::new(state);
+        let response = handler(request);
+        assert_eq!(response.status_code, StatusCode::_500);
+
+        let mut state = Extensions::new();
+        state.insert(String::from("Hello"));
+        let mut request = HttpRequest::new(HttpMethod::GET, String::from("/"));
+        request.state = Arc::new(state);
+        request.extensions.insert(User("ana"));
+        let response = handler(request);
+        assert_eq!(response.content_as_string(), "Hello ana");
+    }
+}
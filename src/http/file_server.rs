@@ -1,15 +1,81 @@
 use crate::http::{HttpContentType, HttpRequest, HttpResponse};
+use std::collections::HashMap;
 use std::fs;
+use std::fs::File;
+use std::io;
+use std::path::{Path, PathBuf};
+
+//What to do with files and folders whose name starts with a dot, like .git or .env
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DotfilePolicy {
+    Deny,   //answer 403
+    Ignore, //answer 404, as if they didn't exist
+    Allow,
+}
+
+#[derive(Debug, Clone)]
+pub struct FileServerOptions {
+    pub dotfiles: DotfilePolicy,
+    //When false, symlinks are only served if they resolve to a file inside the base folder
+    pub follow_external_symlinks: bool,
+    //Extension (lowercase, without the dot) to type, checked before the built-in table
+    pub content_types: HashMap<String, HttpContentType>,
+    //Used for extensions neither table knows
+    pub default_content_type: HttpContentType,
+}
+
+impl FileServerOptions {
+    pub fn with_content_type(mut self, extension: &str, content_type: HttpContentType) -> Self {
+        self.content_types
+            .insert(extension.to_ascii_lowercase(), content_type);
+        self
+    }
+
+    fn content_type_for(&self, path: &Path) -> HttpContentType {
+        let extension = match path.extension().and_then(|x| x.to_str()) {
+            Some(extension) => extension.to_ascii_lowercase(),
+            None => return self.default_content_type.clone(),
+        };
+        match self.content_types.get(&extension) {
+            Some(content_type) => content_type.clone(),
+            None => HttpContentType::from_extension(&extension)
+                .unwrap_or_else(|| self.default_content_type.clone()),
+        }
+    }
+}
+
+impl Default for FileServerOptions {
+    fn default() -> Self {
+        FileServerOptions {
+            dotfiles: DotfilePolicy::Ignore,
+            follow_external_symlinks: true,
+            content_types: HashMap::new(),
+            default_content_type: HttpContentType::APPLICATION_OCTET_STREAM,
+        }
+    }
+}
+
+//Catch-all param that HttpServer::serve_files routes use for the file path
+pub const FILE_PATH_PARAM: &str = "file_path";
 
 pub struct FileServer {
     base_folder: String,
     base_path: String,
+    options: FileServerOptions,
 }
 
 //TODO consider adding caching to the files
 //TODO add templating support
 impl FileServer {
     pub fn new(base_path: String, base_folder: String) -> Self {
+        FileServer::with_options(base_path, base_folder, FileServerOptions::default())
+    }
+
+    pub fn with_options(
+        base_path: String,
+        base_folder: String,
+        options: FileServerOptions,
+    ) -> Self {
         let base_path = if !base_path.starts_with('/') {
             format! {"/{}", base_path}
         } else {
@@ -18,35 +84,118 @@ impl FileServer {
         FileServer {
             base_folder,
             base_path,
+            options,
         }
     }
 
+    //Without the FILE_PATH_PARAM route param, the file path is what follows the base path
     pub fn handle(&self, request: HttpRequest) -> HttpResponse {
-        let sub_path = &request.path[self.base_path.len()..];
-        let file_system_path = format!("{}/{}", self.base_folder, sub_path);
-        println!(
-            "base: {}, called: {}, part: {}",
-            self.base_path, request.path, sub_path
-        );
-        match fs::read(file_system_path) {
-            //TODO how should we handle the content type? Based on file extension with possibility of custom function/hardcoded value?
-            //TODO copying the file into memory and further to the buffer might not be the best option
-            Ok(result) => {
-                HttpResponse::default().with_byte_content(result, HttpContentType::TEXTPLAIN)
-            }
-            //TODO should handle different kinds of error
-            _ => {
+        let request_path = request.path.trim_start_matches('/');
+        let sub_path = match request.route_params.get(FILE_PATH_PARAM) {
+            Some(sub_path) => sub_path.as_str(),
+            None => match strip_base_path(request_path, &self.base_path) {
+                Some(sub_path) => sub_path,
+                None => return HttpResponse::default().not_found(),
+            },
+        };
+        let segments = match normalise(sub_path) {
+            Some(segments) => segments,
+            None => {
+                eprintln!("Refusing path outside of base folder: {}", request.path);
+                return HttpResponse::default().forbidden();
+            }
+        };
+        if segments.iter().any(|x| x.starts_with('.')) {
+            match self.options.dotfiles {
+                DotfilePolicy::Deny => return HttpResponse::default().forbidden(),
+                DotfilePolicy::Ignore => return HttpResponse::default().not_found(),
+                DotfilePolicy::Allow => {}
+            }
+        }
+
+        let mut file_system_path = PathBuf::from(&self.base_folder);
+        file_system_path.extend(&segments);
+        if !self.options.follow_external_symlinks && !self.is_inside_base_folder(&file_system_path)
+        {
+            eprintln!("Refusing symlink outside of base folder: {}", request.path);
+            return HttpResponse::default().forbidden();
+        }
+
+        let content_type = self.options.content_type_for(&file_system_path);
+        let file = File::open(file_system_path).and_then(|file| {
+            let metadata = file.metadata()?;
+            if !metadata.is_file() {
+                return Err(io::Error::from(io::ErrorKind::NotFound));
+            }
+            Ok((file, metadata.len()))
+        });
+        match file {
+            //The file is streamed to the connection instead of being loaded in memory
+            Ok((file, length)) => {
+                HttpResponse::default().with_reader_content(file, Some(length), content_type)
+            }
+            Err(e) if e.kind() == io::ErrorKind::PermissionDenied => {
+                HttpResponse::default().forbidden()
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
                 eprintln!("Can't find file!");
                 HttpResponse::default().not_found()
             }
+            Err(e) => {
+                eprintln!("Error opening file: {}", e);
+                HttpResponse::default().internal_server_error()
+            }
+        }
+    }
+
+    //Symlinks are resolved by canonicalize, so this compares where the file really is
+    fn is_inside_base_folder(&self, file_system_path: &Path) -> bool {
+        match (
+            fs::canonicalize(&self.base_folder),
+            fs::canonicalize(file_system_path),
+        ) {
+            (Ok(base_folder), Ok(file)) => file.starts_with(base_folder),
+            //Missing files are answered with 404 later
+            (_, Err(e)) if e.kind() == io::ErrorKind::NotFound => true,
+            _ => false,
         }
     }
 }
 
+//Resolves '.', '..' and empty segments, returns None if the path would leave the base folder
+fn normalise(path: &str) -> Option<Vec<&str>> {
+    let mut segments = Vec::new();
+    for segment in path.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => {
+                segments.pop()?;
+            }
+            //Separators and NUL from decoded escapes could be interpreted by the OS
+            segment if segment.contains(['\\', '\0']) => return None,
+            segment => segments.push(segment),
+        }
+    }
+    Some(segments)
+}
+
+//The base path only matches whole segments, so "/static" doesn't serve "/staticfoo/x"
+fn strip_base_path<'a>(request_path: &'a str, base_path: &str) -> Option<&'a str> {
+    let base_path = base_path.trim_matches('/');
+    if base_path.is_empty() {
+        return Some(request_path);
+    }
+    request_path
+        .strip_prefix(base_path)
+        .filter(|x| x.is_empty() || x.starts_with('/'))
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::http::file_server::FileServer;
-    use crate::http::{HttpMethod, HttpRequest};
+    use crate::http::file_server::{
+        normalise, DotfilePolicy, FileServer, FileServerOptions, FILE_PATH_PARAM,
+    };
+    use crate::http::{HttpContentType, HttpMethod, HttpRequest, StatusCode};
 
     #[test]
     fn it_serves_present_file() {
@@ -57,6 +206,111 @@ mod tests {
         );
         let response = file_server.handle(request);
 
+        assert_eq!(
+            response.headers.get("Content-Type"),
+            Some("text/plain; charset=utf-8")
+        );
         assert_eq!(response.content_as_string(), "Test content here!\n");
     }
+
+    #[test]
+    fn it_matches_base_path_on_segment_boundary() {
+        let file_server = FileServer::new(String::from("/static"), String::from("."));
+        let request = HttpRequest::new(HttpMethod::GET, String::from("/staticCargo.toml"));
+        assert_eq!(file_server.handle(request).status_code, StatusCode::_404);
+        let request = HttpRequest::new(HttpMethod::GET, String::from("/static/Cargo.toml"));
+        assert_eq!(file_server.handle(request).status_code, StatusCode::_200);
+    }
+
+    #[test]
+    fn it_uses_file_path_param() {
+        let file_server = FileServer::new(String::from("files"), String::from("static/"));
+        let mut request =
+            HttpRequest::new(HttpMethod::GET, String::from("/api/files/test_content.txt"));
+        request.route_params.insert(
+            String::from(FILE_PATH_PARAM),
+            String::from("test_content.txt"),
+        );
+        let response = file_server.handle(request);
+        assert_eq!(response.content_as_string(), "Test content here!\n");
+    }
+
+    #[test]
+    fn it_detects_content_type() {
+        let options = FileServerOptions::default()
+            .with_content_type("toml", HttpContentType::new("application", "toml"));
+        let file_server = FileServer::with_options(String::from("/"), String::from("."), options);
+        let content_type = |path: &str| {
+            let request = HttpRequest::new(HttpMethod::GET, String::from(path));
+            file_server
+                .handle(request)
+                .content_type
+                .unwrap()
+                .to_string()
+        };
+        assert_eq!(content_type("/Cargo.toml"), "application/toml");
+        assert_eq!(content_type("/Readme.md"), "text/markdown; charset=utf-8");
+        assert_eq!(
+            content_type("/load-tests/locustfile.py"),
+            "application/octet-stream"
+        );
+    }
+
+    #[test]
+    fn it_normalises_paths() {
+        assert_eq!(normalise("/a/./b//c/../d").unwrap(), vec!["a", "b", "d"]);
+        assert!(normalise("/a/../../etc/passwd").is_none());
+        assert!(normalise("/a\\..\\b").is_none());
+    }
+
+    #[test]
+    fn it_refuses_paths_outside_base_folder() {
+        let file_server = FileServer::new(String::from("static"), String::from("static"));
+        for path in &["/static/../Cargo.toml", "/static/%2e%2e/Cargo.toml"] {
+            let request = HttpRequest::new(HttpMethod::GET, String::from(*path));
+            let response = file_server.handle(request);
+            assert_eq!(response.status_code, StatusCode::_403);
+        }
+    }
+
+    #[test]
+    fn it_applies_dotfile_policy() {
+        let request = || HttpRequest::new(HttpMethod::GET, String::from("/repo/.gitignore"));
+        let with_policy = |dotfiles| {
+            let options = FileServerOptions {
+                dotfiles,
+                ..FileServerOptions::default()
+            };
+            FileServer::with_options(String::from("repo"), String::from("."), options)
+        };
+
+        let response = with_policy(DotfilePolicy::Deny).handle(request());
+        assert_eq!(response.status_code, StatusCode::_403);
+        let response = with_policy(DotfilePolicy::Ignore).handle(request());
+        assert_eq!(response.status_code, StatusCode::_404);
+        let response = with_policy(DotfilePolicy::Allow).handle(request());
+        assert_eq!(response.status_code, StatusCode::_200);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn it_refuses_external_symlinks_when_configured() {
+        let base_folder = std::env::temp_dir().join("web_server_symlink_test");
+        let _ = std::fs::remove_dir_all(&base_folder);
+        std::fs::create_dir_all(&base_folder).unwrap();
+        let outside = std::fs::canonicalize("Cargo.toml").unwrap();
+        std::os::unix::fs::symlink(outside, base_folder.join("link")).unwrap();
+        let base_folder = String::from(base_folder.to_str().unwrap());
+
+        let request = || HttpRequest::new(HttpMethod::GET, String::from("/files/link"));
+        let file_server = FileServer::new(String::from("files"), base_folder.clone());
+        assert_eq!(file_server.handle(request()).status_code, StatusCode::_200);
+
+        let options = FileServerOptions {
+            follow_external_symlinks: false,
+            ..FileServerOptions::default()
+        };
+        let file_server = FileServer::with_options(String::from("files"), base_folder, options);
+        assert_eq!(file_server.handle(request()).status_code, StatusCode::_403);
+    }
 }
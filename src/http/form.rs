@@ -0,0 +1,197 @@
+use crate::http::extract::require_content_type;
+use crate::http::header_map::CONTENT_TYPE;
+use crate::http::url_encoding::{form_decode_bytes, FromUrlEncoded, UrlEncodedParams};
+use crate::http::{HttpContentType, HttpError, HttpRequest};
+
+//Read by HttpRequest::form from the server state, see HttpServer::set_form_config
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FormConfig {
+    pub max_body_size: usize,  //bytes, checked before anything is decoded
+    pub max_fields: usize,     //number of name=value pairs
+    pub max_field_size: usize, //bytes of a single encoded pair
+}
+
+impl Default for FormConfig {
+    fn default() -> Self {
+        FormConfig {
+            max_body_size: 1024 * 1024,
+            max_fields: 1000,
+            max_field_size: 64 * 1024,
+        }
+    }
+}
+
+//Charsets browsers use for forms, UTF-8 when the Content-Type doesn't say
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FormCharset {
+    Utf8,
+    Latin1,
+}
+
+impl FormCharset {
+    pub fn from_label(label: &str) -> Option<FormCharset> {
+        match label.to_ascii_lowercase().as_str() {
+            "utf-8" | "utf8" | "us-ascii" => Some(FormCharset::Utf8),
+            "iso-8859-1" | "latin1" | "l1" => Some(FormCharset::Latin1),
+            _ => None,
+        }
+    }
+
+    fn decode(&self, bytes: Vec<u8>) -> Option<String> {
+        match self {
+            FormCharset::Utf8 => String::from_utf8(bytes).ok(),
+            FormCharset::Latin1 => Some(bytes.into_iter().map(char::from).collect()),
+        }
+    }
+}
+
+impl HttpRequest {
+    //415 for other content types or charsets, 413 over the limits, 400 for broken escapes and 422 when it doesn't fit T
+    pub fn form<T: FromUrlEncoded>(&self) -> Result<T, HttpError> {
+        let config = self.state::<FormConfig>().copied().unwrap_or_default();
+        self.form_with_config(&config)
+    }
+
+    pub fn form_with_config<T: FromUrlEncoded>(&self, config: &FormConfig) -> Result<T, HttpError> {
+        require_content_type(self, &HttpContentType::APPLICATION_FORM_URLENCODED)?;
+        let content_type = self
+            .headers
+            .get(CONTENT_TYPE)
+            .and_then(HttpContentType::parse);
+        let charset = match content_type.as_ref().and_then(|x| x.charset()) {
+            Some(label) => FormCharset::from_label(label).ok_or_else(|| {
+                HttpError::unsupported_media_type(&format!("Unsupported form charset {}", label))
+            })?,
+            None => FormCharset::Utf8,
+        };
+        let content = self.content.as_deref().unwrap_or_default();
+        let params = parse_form(content, charset, config)?;
+        T::from_url_encoded(params).map_err(|message| HttpError::unprocessable_entity(&message))
+    }
+}
+
+//Unlike query strings, pairs that can't be decoded make the whole form invalid
+pub fn parse_form(
+    content: &[u8],
+    charset: FormCharset,
+    config: &FormConfig,
+) -> Result<UrlEncodedParams, HttpError> {
+    if content.len() > config.max_body_size {
+        return Err(HttpError::payload_too_large(&format!(
+            "Form body is bigger than {} bytes",
+            config.max_body_size
+        )));
+    }
+    let decode = |part: &[u8]| {
+        form_decode_bytes(part)
+            .and_then(|x| charset.decode(x))
+            .ok_or_else(|| HttpError::bad_request("Form field can't be decoded"))
+    };
+    let mut params = UrlEncodedParams::new();
+    for pair in content.split(|x| *x == b'&').filter(|x| !x.is_empty()) {
+        if params.len() == config.max_fields {
+            return Err(HttpError::payload_too_large(&format!(
+                "Form has more than {} fields",
+                config.max_fields
+            )));
+        }
+        if pair.len() > config.max_field_size {
+            return Err(HttpError::payload_too_large(&format!(
+                "Form field is bigger than {} bytes",
+                config.max_field_size
+            )));
+        }
+        let (key, val) = match pair.iter().position(|x| *x == b'=') {
+            Some(i) => (&pair[..i], &pair[i + 1..]),
+            None => (pair, &pair[pair.len()..]),
+        };
+        params.append(&decode(key)?, &decode(val)?);
+    }
+    Ok(params)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::http::form::{parse_form, FormCharset, FormConfig};
+    use crate::http::url_encoding::UrlEncodedParams;
+    use crate::http::{HttpMethod, HttpRequest, StatusCode};
+
+    fn form_request(content_type: &str, content: &[u8]) -> HttpRequest {
+        let mut request = HttpRequest::new(HttpMethod::POST, String::from("/"));
+        request.headers.insert("Content-Type", content_type);
+        request.content = Some(content.to_vec());
+        request
+    }
+
+    #[test]
+    fn it_parses_forms_into_multimap() {
+        let request = form_request(
+            "application/x-www-form-urlencoded",
+            b"tag=a&tag=b&name=Jo%C3%A3o+Silva&empty",
+        );
+        let form: UrlEncodedParams = request.form().unwrap();
+        assert_eq!(form.get_all("tag").collect::<Vec<&str>>(), vec!["a", "b"]);
+        assert_eq!(form.get("name"), Some("João Silva"));
+        assert_eq!(form.get("empty"), Some(""));
+    }
+
+    #[test]
+    fn it_handles_charsets() {
+        let request = form_request(
+            "application/x-www-form-urlencoded; charset=ISO-8859-1",
+            b"name=Jo%E3o&city=\xc9vora",
+        );
+        let form: UrlEncodedParams = request.form().unwrap();
+        assert_eq!(form.get("name"), Some("João"));
+        assert_eq!(form.get("city"), Some("Évora"));
+
+        let request = form_request("application/x-www-form-urlencoded", b"name=Jo%E3o");
+        let error = request.form::<UrlEncodedParams>().err().unwrap();
+        assert_eq!(error.status_code(), StatusCode::_400);
+
+        let request = form_request("application/x-www-form-urlencoded; charset=koi8-r", b"a=b");
+        let error = request.form::<UrlEncodedParams>().err().unwrap();
+        assert_eq!(error.status_code(), StatusCode::_415);
+    }
+
+    #[test]
+    fn it_enforces_limits() {
+        let config = FormConfig {
+            max_body_size: 20,
+            max_fields: 2,
+            max_field_size: 8,
+        };
+        let parse = |content: &[u8]| parse_form(content, FormCharset::Utf8, &config);
+        assert_eq!(parse(b"a=1&b=2").unwrap().len(), 2);
+        let error = parse(b"a=1&b=2&c=3").err().unwrap();
+        assert_eq!(error.status_code(), StatusCode::_413);
+        let error = parse(b"a=123456789").err().unwrap();
+        assert_eq!(error.status_code(), StatusCode::_413);
+        let error = parse(&[b'a'; 21]).err().unwrap();
+        assert_eq!(error.status_code(), StatusCode::_413);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn it_deserializes_typed_forms() {
+        #[derive(serde::Deserialize)]
+        struct Login {
+            user: String,
+            remember: bool,
+            age: Option<u8>,
+        }
+
+        let request = form_request(
+            "application/x-www-form-urlencoded",
+            b"user=ana%26co&remember=true",
+        );
+        let login: Login = request.form().unwrap();
+        assert_eq!(login.user, "ana&co");
+        assert!(login.remember);
+        assert_eq!(login.age, None);
+
+        let request = form_request("application/x-www-form-urlencoded", b"user=ana");
+        let error = request.form::<Login>().err().unwrap();
+        assert_eq!(error.status_code(), StatusCode::_422);
+    }
+}
@@ -0,0 +1,126 @@
+pub const ACCEPT: &str = "Accept";
+pub const ALLOW: &str = "Allow";
+pub const AUTHORIZATION: &str = "Authorization";
+pub const CACHE_CONTROL: &str = "Cache-Control";
+pub const CONNECTION: &str = "Connection";
+pub const CONTENT_DISPOSITION: &str = "Content-Disposition";
+pub const CONTENT_LENGTH: &str = "Content-Length";
+pub const CONTENT_TYPE: &str = "Content-Type";
+pub const COOKIE: &str = "Cookie";
+pub const HOST: &str = "Host";
+pub const LOCATION: &str = "Location";
+pub const ORIGIN: &str = "Origin";
+pub const SET_COOKIE: &str = "Set-Cookie";
+pub const TE: &str = "TE";
+pub const TRAILER: &str = "Trailer";
+pub const TRANSFER_ENCODING: &str = "Transfer-Encoding";
+pub const USER_AGENT: &str = "User-Agent";
+pub const VARY: &str = "Vary";
+
+//Header names are case insensitive and may repeat, so values are kept in the order they were added
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct HeaderMap {
+    entries: Vec<(String, String)>,
+}
+
+impl HeaderMap {
+    pub fn new() -> Self {
+        HeaderMap::default()
+    }
+
+    //First value for the name
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.entries
+            .iter()
+            .find(|x| x.0.eq_ignore_ascii_case(name))
+            .map(|x| x.1.as_str())
+    }
+
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.entries
+            .iter()
+            .filter(move |x| x.0.eq_ignore_ascii_case(name))
+            .map(|x| x.1.as_str())
+    }
+
+    pub fn contains_key(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    //Replaces every existing value for the name
+    pub fn insert(&mut self, name: &str, value: &str) {
+        self.remove(name);
+        self.append(name, value);
+    }
+
+    pub fn append(&mut self, name: &str, value: &str) {
+        self.entries.push((String::from(name), String::from(value)));
+    }
+
+    pub fn remove(&mut self, name: &str) {
+        self.entries.retain(|x| !x.0.eq_ignore_ascii_case(name));
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    //Every name, with the case of its first occurrence, without repetitions
+    pub fn names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = Vec::new();
+        for (name, _) in &self.entries {
+            if !names.iter().any(|x| x.eq_ignore_ascii_case(name)) {
+                names.push(name);
+            }
+        }
+        names
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.entries.iter().map(|x| (x.0.as_str(), x.1.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::http::HeaderMap;
+
+    #[test]
+    fn it_gets_case_insensitive() {
+        let mut headers = HeaderMap::new();
+        headers.insert("Content-Type", "text/plain");
+        assert_eq!(headers.get("content-type"), Some("text/plain"));
+        assert!(headers.contains_key("CONTENT-TYPE"));
+        assert_eq!(headers.get("Accept"), None);
+    }
+
+    #[test]
+    fn it_keeps_repeated_values_in_order() {
+        let mut headers = HeaderMap::new();
+        headers.append("Set-Cookie", "a=1");
+        headers.append("Vary", "Accept");
+        headers.append("set-cookie", "b=2");
+        let cookies: Vec<&str> = headers.get_all("Set-Cookie").collect();
+        assert_eq!(cookies, vec!["a=1", "b=2"]);
+        assert_eq!(headers.names(), vec!["Set-Cookie", "Vary"]);
+        assert_eq!(headers.len(), 3);
+    }
+
+    #[test]
+    fn it_replaces_all_values_on_insert() {
+        let mut headers = HeaderMap::new();
+        headers.append("Accept", "text/html");
+        headers.append("ACCEPT", "text/plain");
+        headers.insert("accept", "*/*");
+        let values: Vec<(&str, &str)> = headers.iter().collect();
+        assert_eq!(values, vec![("accept", "*/*")]);
+    }
+}
@@ -0,0 +1,128 @@
+use crossbeam::channel::Receiver;
+use std::io;
+use std::io::Read;
+
+const READ_CHUNK_SIZE: usize = 8 * 1024;
+
+//Body of a response, either fully in memory or produced while it is being written to the connection
+pub enum HttpBody {
+    Empty,
+    Bytes(Vec<u8>),
+    //The length is optional, when missing the body is sent chunked
+    Reader(Box<dyn Read + Send>, Option<u64>),
+    Chunks(Box<dyn Iterator<Item = Vec<u8>> + Send>),
+    //Every message is sent as a chunk, the body ends when all senders are dropped
+    Channel(Receiver<Vec<u8>>),
+}
+
+impl HttpBody {
+    //Only bodies with a known length can be sent with Content-Length
+    pub fn known_length(&self) -> Option<u64> {
+        match self {
+            HttpBody::Empty => Some(0),
+            HttpBody::Bytes(bytes) => Some(bytes.len() as u64),
+            HttpBody::Reader(_, length) => *length,
+            HttpBody::Chunks(_) | HttpBody::Channel(_) => None,
+        }
+    }
+
+    //Consumes the whole body into memory, mostly useful for tests
+    pub fn into_bytes(self) -> io::Result<Vec<u8>> {
+        let mut result = Vec::new();
+        self.for_each_chunk(|chunk| {
+            result.extend_from_slice(chunk);
+            Ok(())
+        })?;
+        Ok(result)
+    }
+
+    //Calls the consumer with every non empty piece of the body, in order
+    pub fn for_each_chunk<F: FnMut(&[u8]) -> io::Result<()>>(
+        self,
+        mut consumer: F,
+    ) -> io::Result<()> {
+        match self {
+            HttpBody::Empty => Ok(()),
+            HttpBody::Bytes(bytes) if bytes.is_empty() => Ok(()),
+            HttpBody::Bytes(bytes) => consumer(&bytes),
+            HttpBody::Reader(reader, length) => {
+                let mut reader: Box<dyn Read> = match length {
+                    Some(length) => Box::new(reader.take(length)),
+                    None => Box::new(reader),
+                };
+                let mut buffer = vec![0; READ_CHUNK_SIZE];
+                let mut total: u64 = 0;
+                loop {
+                    let read = match reader.read(&mut buffer) {
+                        Ok(0) => break,
+                        Ok(read) => read,
+                        Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                        Err(e) => return Err(e),
+                    };
+                    total += read as u64;
+                    consumer(&buffer[..read])?;
+                }
+                //A short body would leave the client waiting for bytes that never come
+                match length {
+                    Some(length) if length != total => {
+                        Err(io::Error::from(io::ErrorKind::UnexpectedEof))
+                    }
+                    _ => Ok(()),
+                }
+            }
+            HttpBody::Chunks(chunks) => chunks
+                .filter(|x| !x.is_empty())
+                .try_for_each(|x| consumer(&x)),
+            HttpBody::Channel(receiver) => receiver
+                .into_iter()
+                .filter(|x| !x.is_empty())
+                .try_for_each(|x| consumer(&x)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::http::http_body::HttpBody;
+    use crossbeam::channel::unbounded;
+    use std::thread;
+
+    #[test]
+    fn it_reads_reader_body() {
+        let content: &[u8] = b"some content";
+        let body = HttpBody::Reader(Box::new(content), None);
+        assert_eq!(body.known_length(), None);
+        assert_eq!(body.into_bytes().unwrap(), b"some content");
+    }
+
+    #[test]
+    fn it_fails_on_short_reader_body() {
+        let content: &[u8] = b"short";
+        let body = HttpBody::Reader(Box::new(content), Some(10));
+        assert!(body.into_bytes().is_err());
+    }
+
+    #[test]
+    fn it_skips_empty_chunks() {
+        let chunks = vec![b"a".to_vec(), Vec::new(), b"b".to_vec()];
+        let mut received = Vec::new();
+        HttpBody::Chunks(Box::new(chunks.into_iter()))
+            .for_each_chunk(|x| {
+                received.push(x.to_vec());
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(received, vec![b"a".to_vec(), b"b".to_vec()]);
+    }
+
+    #[test]
+    fn it_reads_channel_until_disconnected() {
+        let (s, r) = unbounded();
+        thread::spawn(move || {
+            for part in &["one ", "two"] {
+                s.send(part.as_bytes().to_vec()).unwrap();
+            }
+        });
+        assert_eq!(HttpBody::Channel(r).into_bytes().unwrap(), b"one two");
+    }
+}
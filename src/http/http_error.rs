@@ -0,0 +1,185 @@
+use crate::http::route_param::ParamError;
+use crate::http::{HttpContentType, HttpResponse, StatusCode};
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+//Error returned by handlers, the message is shown to the client while the cause is only logged
+#[derive(Debug)]
+pub struct HttpError {
+    status_code: StatusCode,
+    message: String,
+    cause: Option<Box<dyn Error + Send + Sync>>,
+}
+
+impl HttpError {
+    pub fn new(status_code: StatusCode, message: &str) -> Self {
+        HttpError {
+            status_code,
+            message: String::from(message),
+            cause: None,
+        }
+    }
+
+    pub fn with_cause<E: Into<Box<dyn Error + Send + Sync>>>(mut self, cause: E) -> Self {
+        self.cause = Some(cause.into());
+        self
+    }
+
+    pub fn bad_request(message: &str) -> Self {
+        HttpError::new(StatusCode::_400, message)
+    }
+
+    pub fn unauthorized(message: &str) -> Self {
+        HttpError::new(StatusCode::_401, message)
+    }
+
+    pub fn forbidden(message: &str) -> Self {
+        HttpError::new(StatusCode::_403, message)
+    }
+
+    pub fn not_found(message: &str) -> Self {
+        HttpError::new(StatusCode::_404, message)
+    }
+
+    pub fn conflict(message: &str) -> Self {
+        HttpError::new(StatusCode::_409, message)
+    }
+
+    pub fn payload_too_large(message: &str) -> Self {
+        HttpError::new(StatusCode::_413, message)
+    }
+
+    pub fn unsupported_media_type(message: &str) -> Self {
+        HttpError::new(StatusCode::_415, message)
+    }
+
+    pub fn unprocessable_entity(message: &str) -> Self {
+        HttpError::new(StatusCode::_422, message)
+    }
+
+    //The cause is kept for logging, clients only see the reason phrase
+    pub fn internal<E: Into<Box<dyn Error + Send + Sync>>>(cause: E) -> Self {
+        HttpError::new(StatusCode::_500, StatusCode::_500.to_string()).with_cause(cause)
+    }
+
+    pub fn status_code(&self) -> StatusCode {
+        self.status_code
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    pub fn cause(&self) -> Option<&(dyn Error + Send + Sync + 'static)> {
+        self.cause.as_deref()
+    }
+
+    //Plain text response with the message, used when no error handler is registered
+    pub fn to_response(&self) -> HttpResponse {
+        HttpResponse::default()
+            .with_string_content(&self.message)
+            .with_status(self.status_code)
+    }
+
+    //RFC 7807 problem details, the message is the detail
+    pub fn to_problem_json(&self) -> HttpResponse {
+        let content = format!(
+            "{{\"type\":\"about:blank\",\"title\":{},\"status\":{},\"detail\":{}}}",
+            json_string(self.status_code.to_string()),
+            self.status_code.to_code(),
+            json_string(&self.message)
+        );
+        HttpResponse::default()
+            .with_byte_content(
+                content.into_bytes(),
+                HttpContentType::APPLICATION_PROBLEM_JSON,
+            )
+            .with_status(self.status_code)
+    }
+}
+
+impl fmt::Display for HttpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.status_code.to_code(), self.message)?;
+        if let Some(cause) = &self.cause {
+            write!(f, ": {}", cause)?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for HttpError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match &self.cause {
+            Some(cause) => Some(cause.as_ref()),
+            None => None,
+        }
+    }
+}
+
+impl From<io::Error> for HttpError {
+    fn from(error: io::Error) -> Self {
+        HttpError::internal(error)
+    }
+}
+
+impl From<ParamError> for HttpError {
+    fn from(error: ParamError) -> Self {
+        HttpError::bad_request(&error.to_string()).with_cause(error)
+    }
+}
+
+fn json_string(value: &str) -> String {
+    let mut result = String::with_capacity(value.len() + 2);
+    result.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => result.push_str("\\\""),
+            '\\' => result.push_str("\\\\"),
+            '\n' => result.push_str("\\n"),
+            '\r' => result.push_str("\\r"),
+            '\t' => result.push_str("\\t"),
+            c if (c as u32) < 0x20 => result.push_str(&format!("\\u{:04x}", c as u32)),
+            c => result.push(c),
+        }
+    }
+    result.push('"');
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::http::http_error::HttpError;
+    use crate::http::route_param::ParamError;
+    use crate::http::StatusCode;
+    use std::error::Error;
+    use std::io;
+
+    #[test]
+    fn it_renders_problem_json() {
+        let error = HttpError::bad_request("Missing \"name\"\n");
+        let response = error.to_problem_json();
+        assert_eq!(response.status_code, StatusCode::_400);
+        assert_eq!(
+            response.headers.get("Content-Type").unwrap(),
+            "application/problem+json"
+        );
+        assert_eq!(
+            response.content_as_string(),
+            "{\"type\":\"about:blank\",\"title\":\"Bad Request\",\"status\":400,\"detail\":\"Missing \\\"name\\\"\\n\"}"
+        );
+    }
+
+    #[test]
+    fn it_converts_common_errors() {
+        let error = HttpError::from(io::Error::other("disk on fire"));
+        assert_eq!(error.status_code(), StatusCode::_500);
+        assert_eq!(error.message(), "Internal Server Error");
+        assert_eq!(error.source().unwrap().to_string(), "disk on fire");
+
+        let error = HttpError::from(ParamError::Missing(String::from("id")));
+        assert_eq!(error.status_code(), StatusCode::_400);
+        assert_eq!(error.message(), "Missing route parameter id");
+    }
+}
@@ -0,0 +1,657 @@
+use crate::http::header_map::{CONTENT_LENGTH, TRANSFER_ENCODING};
+use crate::http::url_encoding::percent_decode;
+use crate::http::{split_target, HeaderMap, HttpMethod, HttpRequest, HttpVersion, StatusCode};
+use std::fmt;
+use std::io;
+use std::io::{BufRead, Read};
+
+#[derive(Debug)]
+pub enum RequestParseError {
+    Io(io::Error),
+    MalformedRequestLine(String),
+    MalformedHeader(String),
+    InvalidContentLength(String),
+    MalformedChunk(String),
+    LengthRequired,
+    UnsupportedTransferEncoding(String),
+    UnsupportedVersion(String),
+    BodyTooLarge(usize),
+    RequestLineTooLong,
+    HeadersTooLarge,
+    InvalidUtf8(String),
+}
+
+impl RequestParseError {
+    //Io errors have no status code since the connection can't be answered anymore
+    pub fn status_code(&self) -> Option<StatusCode> {
+        match self {
+            RequestParseError::Io(_) => None,
+            RequestParseError::MalformedRequestLine(_)
+            | RequestParseError::MalformedHeader(_)
+            | RequestParseError::InvalidContentLength(_)
+            | RequestParseError::MalformedChunk(_)
+            | RequestParseError::InvalidUtf8(_) => Some(StatusCode::_400),
+            RequestParseError::LengthRequired => Some(StatusCode::_411),
+            RequestParseError::BodyTooLarge(_) => Some(StatusCode::_413),
+            RequestParseError::RequestLineTooLong => Some(StatusCode::_414),
+            RequestParseError::HeadersTooLarge => Some(StatusCode::_431),
+            RequestParseError::UnsupportedTransferEncoding(_) => Some(StatusCode::_501),
+            RequestParseError::UnsupportedVersion(_) => Some(StatusCode::_505),
+        }
+    }
+}
+
+impl fmt::Display for RequestParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RequestParseError::Io(e) => write!(f, "Error reading request: {}", e),
+            RequestParseError::MalformedRequestLine(line) => {
+                write!(f, "Malformed request line: '{}'", line)
+            }
+            RequestParseError::MalformedHeader(line) => write!(f, "Malformed header: '{}'", line),
+            RequestParseError::InvalidContentLength(val) => {
+                write!(f, "Invalid Content-Length: '{}'", val)
+            }
+            RequestParseError::MalformedChunk(line) => write!(f, "Malformed chunk: '{}'", line),
+            RequestParseError::LengthRequired => {
+                write!(f, "Request body without Content-Length")
+            }
+            RequestParseError::UnsupportedTransferEncoding(coding) => {
+                write!(f, "Transfer encoding not supported: '{}'", coding)
+            }
+            RequestParseError::UnsupportedVersion(version) => {
+                write!(f, "Http version not supported: '{}'", version)
+            }
+            RequestParseError::BodyTooLarge(limit) => {
+                write!(f, "Request body bigger than {} bytes", limit)
+            }
+            RequestParseError::RequestLineTooLong => {
+                write!(f, "Request line longer than {} bytes", MAX_LINE_LENGTH)
+            }
+            RequestParseError::HeadersTooLarge => write!(
+                f,
+                "Header longer than {} bytes or more than {} headers",
+                MAX_LINE_LENGTH, MAX_HEADER_COUNT
+            ),
+            RequestParseError::InvalidUtf8(line) => {
+                write!(f, "Line is not valid UTF-8: '{}'", line)
+            }
+        }
+    }
+}
+
+impl std::error::Error for RequestParseError {}
+
+impl From<io::Error> for RequestParseError {
+    fn from(e: io::Error) -> Self {
+        //Parse errors of the chunked decoder travel through io::Read wrapped in an io::Error
+        if e.get_ref().is_some_and(|x| x.is::<RequestParseError>()) {
+            return *e.into_inner().unwrap().downcast().unwrap();
+        }
+        RequestParseError::Io(e)
+    }
+}
+
+impl From<RequestParseError> for io::Error {
+    fn from(e: RequestParseError) -> Self {
+        match e {
+            RequestParseError::Io(e) => e,
+            e => io::Error::new(io::ErrorKind::InvalidData, e),
+        }
+    }
+}
+
+//Longest request line or header line we accept, including the line break
+pub const MAX_LINE_LENGTH: usize = 8 * 1024;
+//Applies to headers and to trailers separately
+pub const MAX_HEADER_COUNT: usize = 100;
+pub const DEFAULT_MAX_BODY_SIZE: usize = 8 * 1024 * 1024;
+
+//How the end of the body following the headers is found
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum BodyFraming {
+    None,
+    Length(usize),
+    Chunked,
+}
+
+//Returns Ok(None) when the client closed the connection before sending a new request
+//Bodies bigger than max_body_size are refused before they are read into memory
+pub fn read_request<R: BufRead>(
+    reader: &mut R,
+    max_body_size: usize,
+) -> Result<Option<HttpRequest>, RequestParseError> {
+    let (mut http_request, framing) = match read_request_head(reader)? {
+        Some(head) => head,
+        None => return Ok(None),
+    };
+    read_body(reader, &mut http_request, framing, max_body_size)?;
+    Ok(Some(http_request))
+}
+
+//Request line and headers, the body is left in the reader
+pub(crate) fn read_request_head<R: BufRead>(
+    reader: &mut R,
+) -> Result<Option<(HttpRequest, BodyFraming)>, RequestParseError> {
+    let line = match read_line(reader, || RequestParseError::RequestLineTooLong)? {
+        Some(line) => line,
+        None => return Ok(None),
+    };
+
+    let splits: Vec<&str> = line.split(' ').collect();
+    if splits.len() != 3 || splits[1].is_empty() {
+        return Err(RequestParseError::MalformedRequestLine(line));
+    }
+    //The version is checked first since a different protocol might not even have a method
+    let http_version = HttpVersion::from_str(splits[2])
+        .ok_or_else(|| RequestParseError::UnsupportedVersion(String::from(splits[2])))?;
+    if !is_token(splits[0]) {
+        return Err(RequestParseError::MalformedRequestLine(line));
+    }
+    let method = HttpMethod::from_method_string(splits[0]);
+    if percent_decode(split_target(splits[1]).0).is_none() {
+        return Err(RequestParseError::MalformedRequestLine(line));
+    }
+
+    let mut http_request = HttpRequest::new(method, String::from(splits[1]));
+    http_request.http_version = http_version;
+
+    let mut content_length: Option<usize> = None;
+    let mut transfer_encoding: Option<String> = None;
+    //Read headers until we find an empty line
+    while let Some((key, val)) = read_header(reader)? {
+        if http_request.headers.len() == MAX_HEADER_COUNT {
+            return Err(RequestParseError::HeadersTooLarge);
+        }
+        if key.eq_ignore_ascii_case(CONTENT_LENGTH) {
+            match val.parse() {
+                Ok(size) if content_length.is_none() || content_length == Some(size) => {
+                    content_length = Some(size)
+                }
+                _ => return Err(RequestParseError::InvalidContentLength(val)),
+            }
+        }
+        if key.eq_ignore_ascii_case(TRANSFER_ENCODING) {
+            //Repeated Transfer-Encoding headers are one list of codings
+            transfer_encoding = match transfer_encoding {
+                Some(previous) => Some(format!("{}, {}", previous, val.to_lowercase())),
+                None => Some(val.to_lowercase()),
+            };
+        }
+
+        http_request.headers.append(&key, &val);
+    }
+
+    let framing = match (transfer_encoding, content_length) {
+        //Both headers together are a request smuggling vector, so we refuse to pick one
+        (Some(_), Some(_)) => {
+            return Err(RequestParseError::InvalidContentLength(String::from(
+                "sent with Transfer-Encoding",
+            )))
+        }
+        //Chunked is only defined for HTTP/1.1, older clients must tell us the size upfront
+        (Some(_), None) if http_version == HttpVersion::_1_0 => {
+            return Err(RequestParseError::LengthRequired)
+        }
+        (Some(transfer_encoding), None) => {
+            let codings: Vec<&str> = transfer_encoding.split(',').map(|x| x.trim()).collect();
+            if let Some(coding) = codings.iter().find(|x| **x != "chunked") {
+                return Err(RequestParseError::UnsupportedTransferEncoding(
+                    String::from(*coding),
+                ));
+            }
+            if codings.len() != 1 {
+                return Err(RequestParseError::MalformedChunk(transfer_encoding));
+            }
+            BodyFraming::Chunked
+        }
+        (None, Some(size)) => BodyFraming::Length(size),
+        (None, None) => BodyFraming::None,
+    };
+
+    Ok(Some((http_request, framing)))
+}
+
+//Reads the whole body into the request content
+pub(crate) fn read_body<R: BufRead>(
+    reader: &mut R,
+    http_request: &mut HttpRequest,
+    framing: BodyFraming,
+    max_body_size: usize,
+) -> Result<(), RequestParseError> {
+    match framing {
+        BodyFraming::None => {}
+        BodyFraming::Chunked => {
+            let (content, trailers) = read_chunked_body(reader, max_body_size)?;
+            http_request.content = Some(content);
+            http_request.trailers = trailers;
+        }
+        BodyFraming::Length(size) => {
+            if size > max_body_size {
+                return Err(RequestParseError::BodyTooLarge(max_body_size));
+            }
+            //The buffer only grows with what the client actually sends
+            let mut buffer = Vec::new();
+            reader.take(size as u64).read_to_end(&mut buffer)?;
+            if buffer.len() < size {
+                return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+            }
+            http_request.content = Some(buffer);
+        }
+    }
+    Ok(())
+}
+
+//Returns Ok(None) on the empty line that ends a header section
+fn read_header<R: BufRead>(reader: &mut R) -> Result<Option<(String, String)>, RequestParseError> {
+    let line = match read_line(reader, || RequestParseError::HeadersTooLarge)? {
+        Some(line) => line,
+        None => return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into()),
+    };
+
+    if line.is_empty() {
+        return Ok(None);
+    }
+
+    match line.split_once(':') {
+        Some((key, val)) if is_token(key) => {
+            Ok(Some((String::from(key), String::from(val.trim()))))
+        }
+        _ => Err(RequestParseError::MalformedHeader(line)),
+    }
+}
+
+fn read_chunked_body<R: BufRead>(
+    reader: &mut R,
+    max_body_size: usize,
+) -> Result<(Vec<u8>, HeaderMap), RequestParseError> {
+    let mut decoder = ChunkedReader::new(reader);
+    let mut content = Vec::new();
+    //One byte over the limit tells a body of exactly max_body_size apart from a bigger one
+    let limit = (max_body_size as u64).saturating_add(1);
+    (&mut decoder).take(limit).read_to_end(&mut content)?;
+    if content.len() > max_body_size {
+        return Err(RequestParseError::BodyTooLarge(max_body_size));
+    }
+    Ok((content, decoder.trailers))
+}
+
+enum ChunkState {
+    Size,
+    Data(u64),
+    DataEnd,
+    Done,
+}
+
+//Decodes a chunked body while it is read, so the caller decides how much of it ends up in memory
+//Each chunk is "<hex size>[;extensions]" followed by the data, a zero sized chunk ends the body and is followed by the trailers
+pub(crate) struct ChunkedReader<R> {
+    inner: R,
+    state: ChunkState,
+    pub(crate) trailers: HeaderMap,
+}
+
+impl<R: BufRead> ChunkedReader<R> {
+    pub(crate) fn new(inner: R) -> Self {
+        ChunkedReader {
+            inner,
+            state: ChunkState::Size,
+            trailers: HeaderMap::new(),
+        }
+    }
+
+    pub(crate) fn into_inner(self) -> R {
+        self.inner
+    }
+
+    fn read_chunk_line(&mut self) -> Result<String, RequestParseError> {
+        let too_long = || RequestParseError::MalformedChunk(String::from("line too long"));
+        match read_line(&mut self.inner, too_long)? {
+            Some(line) => Ok(line),
+            None => Err(io::Error::from(io::ErrorKind::UnexpectedEof).into()),
+        }
+    }
+
+    //Reads the line between two chunks or the size line of the next one
+    fn advance(&mut self) -> Result<(), RequestParseError> {
+        if let ChunkState::DataEnd = self.state {
+            if !self.read_chunk_line()?.is_empty() {
+                return Err(RequestParseError::MalformedChunk(String::from(
+                    "missing CRLF",
+                )));
+            }
+            self.state = ChunkState::Size;
+            return Ok(());
+        }
+
+        let line = self.read_chunk_line()?;
+        //Chunk extensions have no meaning for us, so they are ignored
+        let size = line.split(';').next().unwrap().trim();
+        //from_str_radix would also accept a leading sign, sizes that don't fit in 64 bits are refused
+        let size = match u64::from_str_radix(size, 16) {
+            Ok(parsed) if size.bytes().all(|x| x.is_ascii_hexdigit()) => parsed,
+            _ => return Err(RequestParseError::MalformedChunk(line)),
+        };
+        if size > 0 {
+            self.state = ChunkState::Data(size);
+            return Ok(());
+        }
+
+        while let Some((key, val)) = read_header(&mut self.inner)? {
+            if self.trailers.len() == MAX_HEADER_COUNT {
+                return Err(RequestParseError::HeadersTooLarge);
+            }
+            self.trailers.append(&key, &val);
+        }
+        self.state = ChunkState::Done;
+        Ok(())
+    }
+}
+
+impl<R: BufRead> Read for ChunkedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            match self.state {
+                ChunkState::Done => return Ok(0),
+                ChunkState::Data(remaining) => {
+                    let max = remaining.min(buf.len() as u64) as usize;
+                    let read = self.inner.read(&mut buf[..max])?;
+                    if read == 0 {
+                        return Err(io::ErrorKind::UnexpectedEof.into());
+                    }
+                    self.state = match remaining - read as u64 {
+                        0 => ChunkState::DataEnd,
+                        remaining => ChunkState::Data(remaining),
+                    };
+                    return Ok(read);
+                }
+                ChunkState::Size | ChunkState::DataEnd => self.advance()?,
+            }
+        }
+    }
+}
+
+//Reads a line without its line terminator, accepting both CRLF and bare LF
+//At most MAX_LINE_LENGTH bytes are buffered, longer lines fail with the error from too_long
+//Lines that aren't UTF-8, e.g. Latin-1 header values, are answered with 400
+fn read_line<R: BufRead, E: Fn() -> RequestParseError>(
+    reader: &mut R,
+    too_long: E,
+) -> Result<Option<String>, RequestParseError> {
+    let mut line = Vec::new();
+    let limit = MAX_LINE_LENGTH as u64 + 1;
+    if reader.take(limit).read_until(b'\n', &mut line)? == 0 {
+        return Ok(None);
+    }
+    if !line.ends_with(b"\n") {
+        if line.len() > MAX_LINE_LENGTH {
+            return Err(too_long());
+        }
+        return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+    }
+    line.pop();
+    if line.ends_with(b"\r") {
+        line.pop();
+    }
+    String::from_utf8(line).map(Some).map_err(|e| {
+        RequestParseError::InvalidUtf8(String::from_utf8_lossy(e.as_bytes()).into_owned())
+    })
+}
+
+//Methods and header names are both tokens
+fn is_token(value: &str) -> bool {
+    !value.is_empty()
+        && value
+            .bytes()
+            .all(|x| x.is_ascii_graphic() && !b"\"(),/:;<=>?@[\\]{}".contains(&x))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::http::http_parser::{
+        read_request, RequestParseError, DEFAULT_MAX_BODY_SIZE, MAX_LINE_LENGTH,
+    };
+    use crate::http::{HttpMethod, HttpVersion, StatusCode};
+
+    fn parse(request: &str) -> Result<Option<crate::http::HttpRequest>, RequestParseError> {
+        read_request(&mut request.as_bytes(), DEFAULT_MAX_BODY_SIZE)
+    }
+
+    fn parse_error_status(request: &str) -> Option<StatusCode> {
+        parse(request).err().unwrap().status_code()
+    }
+
+    #[test]
+    fn it_parses_request() {
+        let request =
+            parse("POST /path HTTP/1.0\r\nHost:localhost\r\nContent-Length: 4\r\n\r\nbody")
+                .unwrap()
+                .unwrap();
+        assert_eq!(request.method, HttpMethod::POST);
+        assert_eq!(request.path, "/path");
+        assert_eq!(request.http_version, HttpVersion::_1_0);
+        assert_eq!(request.headers.get("Host").unwrap(), "localhost");
+        assert_eq!(request.content.unwrap(), b"body");
+    }
+
+    #[test]
+    fn it_keeps_repeated_headers() {
+        let request =
+            parse("POST / HTTP/1.1\r\nAccept: a\r\ncontent-length: 1\r\naccept: b\r\n\r\nx")
+                .unwrap()
+                .unwrap();
+        let accept: Vec<&str> = request.headers.get_all("Accept").collect();
+        assert_eq!(accept, vec!["a", "b"]);
+        assert_eq!(request.content.unwrap(), b"x");
+    }
+
+    #[test]
+    fn it_splits_and_decodes_target() {
+        let request = parse("GET /static/my%20file.txt?x=1&y=a+b HTTP/1.1\r\n\r\n")
+            .unwrap()
+            .unwrap();
+        assert_eq!(request.raw_target, "/static/my%20file.txt?x=1&y=a+b");
+        assert_eq!(request.path, "/static/my file.txt");
+        assert_eq!(request.query_string.as_deref(), Some("x=1&y=a+b"));
+        assert_eq!(request.query_params.get("y"), Some("a b"));
+
+        let request = parse("GET http://example.com/path?q HTTP/1.1\r\n\r\n")
+            .unwrap()
+            .unwrap();
+        assert_eq!(request.path, "/path");
+        assert!(request.query_params.contains_key("q"));
+    }
+
+    #[test]
+    fn it_rejects_broken_path_escapes() {
+        assert_eq!(
+            parse_error_status("GET /bad%zz HTTP/1.1\r\n\r\n"),
+            Some(StatusCode::_400)
+        );
+    }
+
+    #[test]
+    fn it_returns_none_on_closed_connection() {
+        assert!(parse("").unwrap().is_none());
+    }
+
+    #[test]
+    fn it_rejects_malformed_request_line() {
+        assert_eq!(
+            parse_error_status("GET /path\r\n\r\n"),
+            Some(StatusCode::_400)
+        );
+        assert_eq!(
+            parse_error_status("GET  HTTP/1.1\r\n\r\n"),
+            Some(StatusCode::_400)
+        );
+    }
+
+    #[test]
+    fn it_rejects_malformed_header() {
+        let status = parse_error_status("GET / HTTP/1.1\r\nNo colon here\r\n\r\n");
+        assert_eq!(status, Some(StatusCode::_400));
+    }
+
+    #[test]
+    fn it_rejects_invalid_content_length() {
+        let status = parse_error_status("POST / HTTP/1.1\r\nContent-Length: abc\r\n\r\n");
+        assert_eq!(status, Some(StatusCode::_400));
+        let status = parse_error_status(
+            "POST / HTTP/1.1\r\nContent-Length: 1\r\nContent-Length: 2\r\n\r\nab",
+        );
+        assert_eq!(status, Some(StatusCode::_400));
+    }
+
+    #[test]
+    fn it_requires_length_for_http_1_0_transfer_encoding() {
+        let status =
+            parse_error_status("POST / HTTP/1.0\r\nTransfer-Encoding: chunked\r\n\r\n0\r\n\r\n");
+        assert_eq!(status, Some(StatusCode::_411));
+    }
+
+    #[test]
+    fn it_rejects_unknown_transfer_encoding() {
+        let status = parse_error_status("POST / HTTP/1.1\r\nTransfer-Encoding: gzip\r\n\r\n");
+        assert_eq!(status, Some(StatusCode::_501));
+    }
+
+    #[test]
+    fn it_rejects_transfer_encoding_with_content_length() {
+        let status = parse_error_status(
+            "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\nContent-Length: 3\r\n\r\n0\r\n\r\n",
+        );
+        assert_eq!(status, Some(StatusCode::_400));
+    }
+
+    #[test]
+    fn it_decodes_chunked_body() {
+        let request = parse(
+            "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n\
+             4;name=value\r\nWiki\r\n\
+             B\r\npedia in \r\n\r\n\
+             0\r\nExpires: never\r\n\r\n",
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(request.content.unwrap(), b"Wikipedia in \r\n");
+        assert_eq!(request.trailers.get("Expires").unwrap(), "never");
+    }
+
+    #[test]
+    fn it_leaves_next_request_after_chunked_body() {
+        let mut reader = "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n\
+                          3\r\nabc\r\n0\r\n\r\n\
+                          GET /next HTTP/1.1\r\n\r\n"
+            .as_bytes();
+        let request = read_request(&mut reader, DEFAULT_MAX_BODY_SIZE)
+            .unwrap()
+            .unwrap();
+        assert_eq!(request.content.unwrap(), b"abc");
+        let request = read_request(&mut reader, DEFAULT_MAX_BODY_SIZE)
+            .unwrap()
+            .unwrap();
+        assert_eq!(request.path, "/next");
+    }
+
+    #[test]
+    fn it_rejects_malformed_chunk() {
+        let status = parse_error_status(
+            "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\nzz\r\nabc\r\n0\r\n\r\n",
+        );
+        assert_eq!(status, Some(StatusCode::_400));
+        let status = parse_error_status(
+            "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n2\r\nabc\r\n0\r\n\r\n",
+        );
+        assert_eq!(status, Some(StatusCode::_400));
+    }
+
+    #[test]
+    fn it_parses_extension_method() {
+        let request = parse("BREW /pot HTTP/1.1\r\n\r\n").unwrap().unwrap();
+        assert_eq!(request.method, HttpMethod::Extension(String::from("BREW")));
+    }
+
+    #[test]
+    fn it_rejects_invalid_method() {
+        assert_eq!(
+            parse_error_status("GE(T / HTTP/1.1\r\n\r\n"),
+            Some(StatusCode::_400)
+        );
+    }
+
+    #[test]
+    fn it_rejects_unknown_version() {
+        assert_eq!(
+            parse_error_status("GET / HTTP/2.0\r\n\r\n"),
+            Some(StatusCode::_505)
+        );
+    }
+
+    #[test]
+    fn it_fails_on_truncated_body() {
+        let error = parse("POST / HTTP/1.1\r\nContent-Length: 10\r\n\r\nabc")
+            .err()
+            .unwrap();
+        assert!(error.status_code().is_none());
+    }
+
+    #[test]
+    fn it_refuses_bodies_over_the_limit() {
+        let request = "POST / HTTP/1.1\r\nContent-Length: 5\r\n\r\nhello";
+        let error = read_request(&mut request.as_bytes(), 4).err().unwrap();
+        assert_eq!(error.status_code(), Some(StatusCode::_413));
+        assert!(read_request(&mut request.as_bytes(), 5).is_ok());
+
+        let request = "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nabc\r\n3\r\ndef\r\n0\r\n\r\n";
+        let error = read_request(&mut request.as_bytes(), 5).err().unwrap();
+        assert_eq!(error.status_code(), Some(StatusCode::_413));
+    }
+
+    #[test]
+    fn it_refuses_huge_chunk_sizes() {
+        let request =
+            "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\nFFFFFFFFFFFFFFFF\r\nabc";
+        let error = read_request(&mut request.as_bytes(), 2).err().unwrap();
+        assert_eq!(error.status_code(), Some(StatusCode::_413));
+        let request =
+            "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n1FFFFFFFFFFFFFFFF\r\nabc";
+        assert_eq!(parse_error_status(request), Some(StatusCode::_400));
+    }
+
+    #[test]
+    fn it_refuses_huge_content_length_without_allocating() {
+        let request = "POST / HTTP/1.1\r\nContent-Length: 99999999999\r\n\r\nx";
+        assert_eq!(parse_error_status(request), Some(StatusCode::_413));
+        let request = "POST / HTTP/1.1\r\nContent-Length: 5\r\n\r\nabc";
+        assert!(matches!(parse(request), Err(RequestParseError::Io(_))));
+    }
+
+    #[test]
+    fn it_rejects_non_utf8_lines() {
+        let request = b"GET / HTTP/1.1\r\nX-Name: caf\xe9\r\n\r\n";
+        let error = read_request(&mut &request[..], DEFAULT_MAX_BODY_SIZE)
+            .err()
+            .unwrap();
+        assert!(matches!(error, RequestParseError::InvalidUtf8(_)));
+        assert_eq!(error.status_code(), Some(StatusCode::_400));
+    }
+
+    #[test]
+    fn it_limits_line_length_and_header_count() {
+        let long_target = format!("GET /{} HTTP/1.1\r\n\r\n", "a".repeat(MAX_LINE_LENGTH));
+        assert_eq!(parse_error_status(&long_target), Some(StatusCode::_414));
+        let long_header = format!(
+            "GET / HTTP/1.1\r\nX-Big: {}\r\n\r\n",
+            "a".repeat(MAX_LINE_LENGTH)
+        );
+        assert_eq!(parse_error_status(&long_header), Some(StatusCode::_431));
+        let many_headers = format!("GET / HTTP/1.1\r\n{}\r\n", "X-A: 1\r\n".repeat(101));
+        assert_eq!(parse_error_status(&many_headers), Some(StatusCode::_431));
+        let enough_headers = format!("GET / HTTP/1.1\r\n{}\r\n", "X-A: 1\r\n".repeat(100));
+        assert!(parse(&enough_headers).is_ok());
+    }
+}
@@ -1,161 +1,1061 @@
-use crate::http::{HttpMethod, HttpRequest, HttpResponse};
-use enum_iterator::IntoEnumIterator;
-use std::borrow::{Borrow, BorrowMut};
+use crate::http::header_map::ALLOW;
+use crate::http::middleware::{run_middlewares, Middleware};
+use crate::http::route_param::{parse_param_segment, ParamConstraint};
+use crate::http::url_encoding::{percent_decode, percent_encode};
+use crate::http::{
+    HttpContentType, HttpError, HttpMethod, HttpRequest, HttpResponse, IntoResponse,
+};
 use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
 use std::sync::Arc;
 
 //TODO what should we have here? Should http request handle a drop so we know when it goes out of context we should write the result?
 pub type HttpRouteHandler = dyn Fn(HttpRequest) -> HttpResponse + Send + Sync;
 
-type Routes = HashMap<String, HttpRouteNode>;
+pub type HttpErrorHandler = dyn Fn(HttpError) -> HttpResponse + Send + Sync;
 
-trait RouteAdd {
-    fn on(&mut self, original_path: &str, path: &[&str], handler: Arc<HttpRouteHandler>);
+//Adapts handlers returning anything that implements IntoResponse, e.g. Result<HttpResponse, HttpError>
+pub fn into_route_handler<T, R>(handler: T) -> Arc<HttpRouteHandler>
+where
+    T: Fn(HttpRequest) -> R + Send + Sync + 'static,
+    R: IntoResponse,
+{
+    Arc::new(move |request| handler(request).into_response())
 }
-impl RouteAdd for Routes {
-    fn on(&mut self, original_path: &str, path: &[&str], handler: Arc<HttpRouteHandler>) {
-        let child_key = String::from(path[0]);
-        let child = self
-            .entry(child_key)
-            .or_insert_with(HttpRouteNode::new)
-            .borrow_mut();
-        child.on(original_path, path, handler);
-    }
+
+//Handler of a route together with its pattern, as registered without the leading slash
+#[derive(Clone)]
+struct RouteTarget {
+    handler: Arc<HttpRouteHandler>,
+    pattern: Arc<str>,
 }
 
+//Each segment is tried as a static path first, then as each parameter and finally as a wildcard,
+//so the matched route never depends on registration or hashing order
 struct HttpRouteNode {
-    handler: Option<Arc<HttpRouteHandler>>,
-    children: Routes,
-    var_name: Option<String>,
-    wildcard: bool,
+    handler: Option<RouteTarget>,
+    static_children: HashMap<String, HttpRouteNode>,
+    param_children: Vec<HttpParamNode>, //constrained params first, then in registration order
+    wildcard_handler: Option<RouteTarget>,
+    wildcard_name: Option<String>, //"*rest" captures the remaining path as "rest"
+}
+
+struct HttpParamNode {
+    var_name: String,
+    constraint_source: String, //as written in the route, params only share a node if it is the same
+    constraint: ParamConstraint,
+    node: HttpRouteNode,
 }
 
 impl HttpRouteNode {
     fn new() -> HttpRouteNode {
         HttpRouteNode {
             handler: None,
-            children: HashMap::new(),
-            var_name: None,
-            wildcard: false,
+            static_children: HashMap::new(),
+            param_children: Vec::new(),
+            wildcard_handler: None,
+            wildcard_name: None,
         }
     }
 
-    pub fn on(&mut self, original_path: &str, path: &[&str], handler: Arc<HttpRouteHandler>) {
-        let self_path_part = path[0];
-        if self_path_part.starts_with('?') {
-            self.var_name = Some(String::from(&self_path_part[1..]));
-        }
-        if self_path_part == "*" {
-            self.wildcard = true;
-            if path.len() > 1 {
-                panic!(
-                    "Last '*' for path part that is not the last: {}",
-                    original_path
-                )
+    //The path was validated by HttpRouter::on, a None handler removes the route
+    fn on(&mut self, path: &[String], handler: Option<RouteTarget>) {
+        let (path_part, rest) = match path.split_first() {
+            Some(x) => x,
+            None => {
+                self.handler = handler;
+                return;
             }
+        };
+        if let Some(wildcard_name) = path_part.strip_prefix('*') {
+            self.wildcard_handler = handler;
+            self.wildcard_name = match wildcard_name {
+                "" => None,
+                wildcard_name => Some(String::from(wildcard_name)),
+            };
+        } else if let Some((var_name, constraint)) = parse_param_segment(path_part) {
+            let position = self
+                .param_children
+                .iter()
+                .position(|x| x.var_name == var_name && x.constraint_source == constraint);
+            let position = match position {
+                Some(position) => position,
+                None => self.add_param_child(var_name, constraint),
+            };
+            self.param_children[position].node.on(rest, handler);
+        } else {
+            self.static_children
+                .entry(path_part.clone())
+                .or_insert_with(HttpRouteNode::new)
+                .on(rest, handler);
         }
-        if path.len() > 1 {
-            self.children.on(original_path, &path[1..], handler);
+    }
+
+    fn add_param_child(&mut self, var_name: &str, constraint: &str) -> usize {
+        let param_node = HttpParamNode {
+            var_name: String::from(var_name),
+            constraint_source: String::from(constraint),
+            constraint: ParamConstraint::parse(constraint).expect("Constraint already validated"),
+            node: HttpRouteNode::new(),
+        };
+        let position = if param_node.constraint.is_any() {
+            self.param_children.len()
         } else {
-            self.handler = Some(handler);
+            let first_any = self
+                .param_children
+                .iter()
+                .position(|x| x.constraint.is_any());
+            first_any.unwrap_or(self.param_children.len())
+        };
+        self.param_children.insert(position, param_node);
+        position
+    }
+
+    //Backtracks to the next candidate when a deeper segment fails to match
+    fn find<'a>(
+        &'a self,
+        segments: &[String],
+        route_params: &mut Vec<(&'a str, String)>,
+    ) -> Option<&'a RouteTarget> {
+        let (segment, rest) = match segments.split_first() {
+            Some(x) => x,
+            None => return self.handler.as_ref(),
+        };
+        if let Some(child) = self.static_children.get(segment) {
+            if let Some(handler) = child.find(rest, route_params) {
+                return Some(handler);
+            }
+        }
+        //Parameters never match an empty segment, e.g. the one after a trailing slash
+        if !segment.is_empty() {
+            for param_node in &self.param_children {
+                if !param_node.constraint.matches(segment) {
+                    continue;
+                }
+                route_params.push((&param_node.var_name, segment.clone()));
+                if let Some(handler) = param_node.node.find(rest, route_params) {
+                    return Some(handler);
+                }
+                route_params.pop();
+            }
         }
+        let handler = self.wildcard_handler.as_ref()?;
+        if let Some(wildcard_name) = &self.wildcard_name {
+            route_params.push((wildcard_name, segments.join("/")));
+        }
+        Some(handler)
     }
 }
 
+//What to do when a path only matches a route once its trailing slash is added or removed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TrailingSlash {
+    #[default]
+    Strict, //"/users/" and "/users" are different routes
+    Ignore,   //the handler of the other route is called
+    Redirect, //answers 308 with the other path, so the method and body are kept
+}
+
+//Settings mounted routers take from their parent unless they set their own
+#[derive(Clone, Copy)]
+struct Inherited<'a> {
+    not_found_handler: Option<&'a Arc<HttpRouteHandler>>,
+    error_handler: Option<&'a Arc<HttpErrorHandler>>,
+    trailing_slash: TrailingSlash,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RouteError {
+    InvalidPath {
+        path: String,
+        reason: String,
+    },
+    Duplicate {
+        method: HttpMethod,
+        path: String,
+    },
+    //Both routes match exactly the same requests, e.g. "/a/{x}" and "/a/{y}"
+    Ambiguous {
+        method: HttpMethod,
+        path: String,
+        existing: String,
+    },
+    DuplicateName(String),
+    DuplicateMount(String),
+}
+
+impl fmt::Display for RouteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RouteError::InvalidPath { path, reason } => {
+                write!(f, "Invalid route /{}: {}", path, reason)
+            }
+            RouteError::Duplicate { method, path } => {
+                write!(f, "Duplicate route {} /{}", method.to_string(), path)
+            }
+            RouteError::Ambiguous {
+                method,
+                path,
+                existing,
+            } => write!(
+                f,
+                "Route {} /{} is ambiguous with /{}",
+                method.to_string(),
+                path,
+                existing
+            ),
+            RouteError::DuplicateName(name) => write!(f, "Duplicate route name {}", name),
+            RouteError::DuplicateMount(prefix) => {
+                write!(f, "Router already mounted at /{}", prefix)
+            }
+        }
+    }
+}
+
+impl Error for RouteError {}
+
+//A registered route, optional segments make it match more than one path in the tree
+struct RouteEntry {
+    method: HttpMethod,
+    pattern: String,
+    name: Option<String>,
+    variants: Vec<Vec<String>>,
+}
+
+#[derive(Default)]
 pub struct HttpRouter {
-    roots: HashMap<HttpMethod, Routes>,
+    roots: HashMap<HttpMethod, HttpRouteNode>,
+    routes: Vec<RouteEntry>, //in registration order
+    allow_overrides: bool,
     not_found_handler: Option<Arc<HttpRouteHandler>>,
+    error_handler: Option<Arc<HttpErrorHandler>>,
+    trailing_slash: Option<TrailingSlash>,
+    middlewares: Vec<(Vec<String>, Arc<dyn Middleware>)>, //path prefix, empty for all requests
+    mounts: Vec<(Vec<String>, HttpRouter)>,
+    route_table_path: Option<Vec<String>>,
 }
-impl Default for HttpRouter {
-    fn default() -> Self {
-        let mut roots = HashMap::new();
 
-        for method in HttpMethod::into_enum_iter() {
-            roots.insert(method, HashMap::new());
+impl HttpRouter {
+    //Fails for invalid paths and for routes that would hide or be hidden by another one
+    pub fn on(
+        &mut self,
+        method: HttpMethod,
+        path: &str,
+        handler: Arc<HttpRouteHandler>,
+    ) -> Result<(), RouteError> {
+        self.add(method, path, None, handler)
+    }
+
+    //Named routes can be listed by name and turned back into paths with url_for
+    pub fn on_named(
+        &mut self,
+        method: HttpMethod,
+        path: &str,
+        name: &str,
+        handler: Arc<HttpRouteHandler>,
+    ) -> Result<(), RouteError> {
+        self.add(method, path, Some(name), handler)
+    }
+
+    fn add(
+        &mut self,
+        method: HttpMethod,
+        path: &str,
+        name: Option<&str>,
+        handler: Arc<HttpRouteHandler>,
+    ) -> Result<(), RouteError> {
+        let path = path.strip_prefix('/').unwrap_or(path);
+        let variants = route_variants(path)?;
+        let shapes: Vec<String> = variants.iter().map(|x| route_shape(x)).collect();
+        let conflicting = |entry_method: &HttpMethod, variant: &[String]| {
+            *entry_method == method && shapes.contains(&route_shape(variant))
+        };
+
+        if let Some(name) = name {
+            let name_taken = self.routes.iter().any(|x| x.name.as_deref() == Some(name));
+            if name_taken && !self.allow_overrides {
+                return Err(RouteError::DuplicateName(String::from(name)));
+            }
+            for entry in self
+                .routes
+                .iter_mut()
+                .filter(|x| x.name.as_deref() == Some(name))
+            {
+                entry.name = None;
+            }
         }
 
-        HttpRouter {
-            roots,
-            not_found_handler: None,
+        if !self.allow_overrides {
+            //Routes of mounted routers count too, they would hide the new one
+            let existing = self
+                .nested_routes()
+                .into_iter()
+                .find(|(entry_method, _, variants)| {
+                    variants.iter().any(|x| conflicting(entry_method, x))
+                });
+            return match existing {
+                Some((_, existing, _)) if existing == path => Err(RouteError::Duplicate {
+                    method,
+                    path: String::from(path),
+                }),
+                Some((_, existing, _)) => Err(RouteError::Ambiguous {
+                    method,
+                    path: String::from(path),
+                    existing,
+                }),
+                None => {
+                    self.add_route(method, path, name, variants, handler);
+                    Ok(())
+                }
+            };
         }
+
+        //The new route replaces every path of the older routes it conflicts with
+        self.remove_conflicting(&method, &shapes);
+        self.add_route(method, path, name, variants, handler);
+        Ok(())
     }
-}
 
-impl HttpRouter {
-    pub fn on(&mut self, method: HttpMethod, mut path: &str, handler: Arc<HttpRouteHandler>) {
-        if path.starts_with('/') {
-            path = &path[1..];
+    //Removes the paths of routes matching exactly the same requests, mounted routers included
+    fn remove_conflicting(&mut self, method: &HttpMethod, shapes: &[String]) {
+        let conflicting = |entry_method: &HttpMethod, variant: &[String]| {
+            entry_method == method && shapes.contains(&route_shape(variant))
+        };
+        if let Some(root) = self.roots.get_mut(method) {
+            for entry in &mut self.routes {
+                let entry_method = entry.method.clone();
+                let (removed, kept) = entry
+                    .variants
+                    .drain(..)
+                    .partition(|x| conflicting(&entry_method, x));
+                entry.variants = kept;
+                for variant in removed {
+                    root.on(&variant, None);
+                }
+            }
         }
-        let parts: Vec<&str> = path.split('/').collect();
-        let routes = self.roots.get_mut(&method).unwrap();
-        routes.on(path, parts.borrow(), handler);
-    }
-
-    pub fn handle(&self, mut http_request: HttpRequest) -> HttpResponse {
-        let mut routes = self.roots.get(http_request.method.borrow()).unwrap();
-        let mut node: Option<&HttpRouteNode> = None;
-        let mut path = http_request.path.as_str();
-        if path.starts_with('/') {
-            path = &path[1..];
-        }
-        for part in path.split('/') {
-            if let Some(inner_node) = routes.get(part) {
-                node = Some(inner_node);
-                routes = &inner_node.children;
-            } else if let Some(inner_node) = routes
+        self.routes.retain(|x| !x.variants.is_empty());
+        for (prefix, router) in &mut self.mounts {
+            let shapes: Vec<String> = shapes
                 .iter()
-                .map(|x| x.1)
-                .find(|x| x.wildcard || x.var_name.is_some())
-            {
-                if let Some(var_name) = &inner_node.var_name {
-                    http_request
-                        .route_params
-                        .insert(var_name.clone(), String::from(part));
-                }
-                node = Some(inner_node);
-                if inner_node.wildcard {
-                    break;
+                .filter_map(|x| strip_shape_prefix(x, prefix))
+                .collect();
+            router.remove_conflicting(method, &shapes);
+        }
+    }
+
+    //Every route as (method, pattern, variants) relative to this router, including the ones of mounted routers
+    fn nested_routes(&self) -> Vec<(HttpMethod, String, Vec<Vec<String>>)> {
+        let mut routes: Vec<(HttpMethod, String, Vec<Vec<String>>)> = self
+            .routes
+            .iter()
+            .map(|x| (x.method.clone(), x.pattern.clone(), x.variants.clone()))
+            .collect();
+        for (prefix, router) in &self.mounts {
+            for (method, pattern, variants) in router.nested_routes() {
+                let variants = variants
+                    .into_iter()
+                    .map(|x| prefixed_variant(prefix, x))
+                    .collect();
+                routes.push((method, prefixed_pattern(prefix, &pattern), variants));
+            }
+        }
+        routes
+    }
+
+    fn add_route(
+        &mut self,
+        method: HttpMethod,
+        path: &str,
+        name: Option<&str>,
+        variants: Vec<Vec<String>>,
+        handler: Arc<HttpRouteHandler>,
+    ) {
+        let target = RouteTarget {
+            handler,
+            pattern: Arc::from(path),
+        };
+        let root = self
+            .roots
+            .entry(method.clone())
+            .or_insert_with(HttpRouteNode::new);
+        for variant in &variants {
+            root.on(variant, Some(target.clone()));
+        }
+        self.routes.push(RouteEntry {
+            method,
+            pattern: String::from(path),
+            name: name.map(String::from),
+            variants,
+        });
+    }
+
+    //Every registered route as (method, pattern, name), including the ones of mounted routers
+    pub fn routes(&self) -> impl Iterator<Item = (HttpMethod, String, Option<String>)> {
+        let mut routes = Vec::new();
+        self.collect_routes("", &mut routes);
+        routes.into_iter()
+    }
+
+    fn collect_routes(&self, prefix: &str, routes: &mut Vec<(HttpMethod, String, Option<String>)>) {
+        for entry in &self.routes {
+            let pattern = mounted_pattern(prefix, &entry.pattern);
+            routes.push((entry.method.clone(), pattern, entry.name.clone()));
+        }
+        for (mount_prefix, router) in &self.mounts {
+            let prefix = format!("{}/{}", prefix, mount_prefix.join("/"));
+            router.collect_routes(&prefix, routes);
+        }
+    }
+
+    //Builds the path of a named route, None if there is no such route or a param is missing or
+    //doesn't satisfy its constraint. Optional params are left out from the first missing one
+    pub fn url_for(&self, name: &str, params: &[(&str, &str)]) -> Option<String> {
+        if let Some(entry) = self.routes.iter().find(|x| x.name.as_deref() == Some(name)) {
+            return build_path(&entry.pattern, params);
+        }
+        self.mounts.iter().find_map(|(prefix, router)| {
+            let path = router.url_for(name, params)?;
+            let prefix: Vec<String> = prefix.iter().map(|x| percent_encode(x)).collect();
+            match path.as_str() {
+                "/" => Some(format!("/{}", prefix.join("/"))),
+                path => Some(format!("/{}{}", prefix.join("/"), path)),
+            }
+        })
+    }
+
+    //Answers GET requests to the path with an HTML table of every route, meant for development
+    pub fn serve_route_table(&mut self, path: &str) {
+        self.route_table_path = Some(path_segments(path));
+    }
+
+    fn route_table(&self) -> HttpResponse {
+        let mut rows = String::new();
+        for (method, pattern, name) in self.routes() {
+            rows.push_str(&format!(
+                "<tr><td>{}</td><td>{}</td><td>{}</td></tr>\n",
+                escape_html(method.to_string()),
+                escape_html(&pattern),
+                escape_html(name.as_deref().unwrap_or(""))
+            ));
+        }
+        let content = format!(
+            "<!DOCTYPE html>\n<html>\n<head><title>Routes</title></head>\n<body>\n<table>\n\
+             <tr><th>Method</th><th>Pattern</th><th>Name</th></tr>\n{}</table>\n</body>\n</html>\n",
+            rows
+        );
+        HttpResponse::default().with_byte_content(content.into_bytes(), HttpContentType::TEXT_HTML)
+    }
+
+    //Registering a route that conflicts with an existing one replaces it instead of failing
+    pub fn set_allow_overrides(&mut self, allow_overrides: bool) {
+        self.allow_overrides = allow_overrides;
+    }
+
+    //Used by the convenience methods, a misconfigured route should stop the server from starting
+    pub(crate) fn on_or_panic(
+        &mut self,
+        method: HttpMethod,
+        path: &str,
+        handler: Arc<HttpRouteHandler>,
+    ) {
+        if let Err(e) = self.on(method, path, handler) {
+            panic!("{}", e);
+        }
+    }
+
+    //Applies to mounted routers too, unless they set their own
+    pub fn set_trailing_slash(&mut self, trailing_slash: TrailingSlash) {
+        self.trailing_slash = Some(trailing_slash);
+    }
+
+    pub fn get<T, R>(&mut self, path: &str, handler: T)
+    where
+        T: Fn(HttpRequest) -> R + Send + Sync + 'static,
+        R: IntoResponse,
+    {
+        self.on_or_panic(HttpMethod::GET, path, into_route_handler(handler));
+    }
+
+    pub fn post<T, R>(&mut self, path: &str, handler: T)
+    where
+        T: Fn(HttpRequest) -> R + Send + Sync + 'static,
+        R: IntoResponse,
+    {
+        self.on_or_panic(HttpMethod::POST, path, into_route_handler(handler));
+    }
+
+    pub fn put<T, R>(&mut self, path: &str, handler: T)
+    where
+        T: Fn(HttpRequest) -> R + Send + Sync + 'static,
+        R: IntoResponse,
+    {
+        self.on_or_panic(HttpMethod::PUT, path, into_route_handler(handler));
+    }
+
+    pub fn delete<T, R>(&mut self, path: &str, handler: T)
+    where
+        T: Fn(HttpRequest) -> R + Send + Sync + 'static,
+        R: IntoResponse,
+    {
+        self.on_or_panic(HttpMethod::DELETE, path, into_route_handler(handler));
+    }
+
+    pub fn patch<T, R>(&mut self, path: &str, handler: T)
+    where
+        T: Fn(HttpRequest) -> R + Send + Sync + 'static,
+        R: IntoResponse,
+    {
+        self.on_or_panic(HttpMethod::PATCH, path, into_route_handler(handler));
+    }
+
+    //Every request under the prefix is handled by the router, its paths are relative to the prefix.
+    //Fails like on when a mounted route matches the same requests as an existing one, or the prefix is already mounted
+    pub fn mount(&mut self, prefix: &str, router: HttpRouter) -> Result<(), RouteError> {
+        let prefix = path_segments(prefix);
+        let same_prefix = self.mounts.iter().position(|(x, _)| *x == prefix);
+        if same_prefix.is_some() && !self.allow_overrides {
+            return Err(RouteError::DuplicateMount(prefix.join("/")));
+        }
+        let mounted: Vec<(HttpMethod, String, Vec<String>)> = router
+            .nested_routes()
+            .into_iter()
+            .map(|(method, pattern, variants)| {
+                let shapes = variants
+                    .into_iter()
+                    .map(|x| route_shape(&prefixed_variant(&prefix, x)))
+                    .collect();
+                (method, prefixed_pattern(&prefix, &pattern), shapes)
+            })
+            .collect();
+
+        if self.allow_overrides {
+            if let Some(i) = same_prefix {
+                self.mounts.remove(i);
+            }
+            for (method, _, shapes) in &mounted {
+                self.remove_conflicting(method, shapes);
+            }
+        } else {
+            let existing = self.nested_routes();
+            for (method, path, shapes) in mounted {
+                let found = existing.iter().find(|(entry_method, _, variants)| {
+                    *entry_method == method
+                        && variants.iter().any(|x| shapes.contains(&route_shape(x)))
+                });
+                match found {
+                    Some((_, existing, _)) if *existing == path => {
+                        return Err(RouteError::Duplicate { method, path })
+                    }
+                    Some((_, existing, _)) => {
+                        return Err(RouteError::Ambiguous {
+                            method,
+                            path,
+                            existing: existing.clone(),
+                        })
+                    }
+                    None => {}
                 }
+            }
+        }
+        self.mounts.push((prefix, router));
+        Ok(())
+    }
+
+    //Builds a router with its own middlewares and not found handler, mounted at the prefix
+    pub fn scope<F: FnOnce(&mut HttpRouter)>(
+        &mut self,
+        prefix: &str,
+        build: F,
+    ) -> Result<(), RouteError> {
+        let mut router = HttpRouter::default();
+        build(&mut router);
+        self.mount(prefix, router)
+    }
+
+    //Runs for every request, including the ones without a route
+    pub fn middleware(&mut self, middleware: Arc<dyn Middleware>) {
+        self.middlewares.push((Vec::new(), middleware));
+    }
+
+    //Runs for every request whose path is the prefix or starts with it as whole segments
+    pub fn middleware_for(&mut self, prefix: &str, middleware: Arc<dyn Middleware>) {
+        self.middlewares.push((path_segments(prefix), middleware));
+    }
+
+    pub fn handle(&self, http_request: HttpRequest) -> HttpResponse {
+        let raw_path = String::from(http_request.raw_path());
+        let inherited = Inherited {
+            not_found_handler: None,
+            error_handler: None,
+            trailing_slash: TrailingSlash::default(),
+        };
+        self.handle_path(http_request, &raw_path, "", inherited)
+    }
+
+    //The raw path is relative to this router, mounted routers only see what follows their prefix.
+    //The route prefix is where this router is mounted, e.g. "/api", used to name the matched route.
+    //Middlewares run in registration order, the first registered is the outermost
+    fn handle_path(
+        &self,
+        http_request: HttpRequest,
+        raw_path: &str,
+        route_prefix: &str,
+        parent: Inherited,
+    ) -> HttpResponse {
+        let raw_path = raw_path.trim_start_matches('/');
+        let inherited = Inherited {
+            not_found_handler: self.not_found_handler.as_ref().or(parent.not_found_handler),
+            error_handler: self.error_handler.as_ref().or(parent.error_handler),
+            trailing_slash: self.trailing_slash.unwrap_or(parent.trailing_slash),
+        };
+        if self.middlewares.is_empty() {
+            let response = self.dispatch(http_request, raw_path, route_prefix, inherited);
+            return render_error(response, inherited.error_handler);
+        }
+        let segments =
+            path_segments(&percent_decode(raw_path).unwrap_or_else(|| String::from(raw_path)));
+        let middlewares: Vec<Arc<dyn Middleware>> = self
+            .middlewares
+            .iter()
+            .filter(|(prefix, _)| starts_with_segments(&segments, prefix))
+            .map(|(_, middleware)| middleware.clone())
+            .collect();
+        let response = run_middlewares(&middlewares, http_request, &|x| {
+            self.dispatch(x, raw_path, route_prefix, inherited)
+        });
+        render_error(response, inherited.error_handler)
+    }
+
+    fn dispatch(
+        &self,
+        mut http_request: HttpRequest,
+        path: &str,
+        route_prefix: &str,
+        inherited: Inherited,
+    ) -> HttpResponse {
+        if let Some(route_table_path) = &self.route_table_path {
+            let is_get =
+                http_request.method == HttpMethod::GET || http_request.method == HttpMethod::HEAD;
+            let segments =
+                path_segments(&percent_decode(path).unwrap_or_else(|| String::from(path)));
+            if is_get && segments == *route_table_path {
+                return self.route_table();
+            }
+        }
+
+        //Routes of this router under a mounted prefix stay reachable for paths the mounted router doesn't know
+        if let Some((mount_prefix, router, rest)) = self.find_mount(path) {
+            if router.has_route(&rest) || !self.has_own_route(path) {
+                let route_prefix = match mount_prefix.join("/") {
+                    mount_prefix if mount_prefix.is_empty() => String::from(route_prefix),
+                    mount_prefix => format!("{}/{}", route_prefix, mount_prefix),
+                };
+                return router.handle_path(http_request, &rest, &route_prefix, inherited);
+            }
+        }
+
+        let segments = decode_segments(path);
+        if let Some((target, route_params)) = self.find_handler(&http_request.method, &segments) {
+            http_request.route_params.extend(route_params);
+            http_request.matched_route = Some(mounted_pattern(route_prefix, &target.pattern));
+            return (target.handler)(http_request);
+        }
+
+        //Extension methods that no route uses are not implemented at all by this server
+        if let HttpMethod::Extension(_) = http_request.method {
+            if !self.roots.contains_key(&http_request.method) {
+                return HttpResponse::default().not_implemented();
+            }
+        }
+
+        let allowed_methods = self.allowed_methods(&segments);
+        if http_request.method == HttpMethod::OPTIONS
+            && (!allowed_methods.is_empty() || http_request.path == "*")
+        {
+            let allowed_methods = if http_request.path == "*" {
+                self.registered_methods()
             } else {
-                return self.handle_not_found(http_request);
+                allowed_methods
+            };
+            return HttpResponse::default()
+                .ok()
+                .with_header(String::from(ALLOW), allow_header(&allowed_methods));
+        }
+        if !allowed_methods.is_empty() {
+            return HttpResponse::default()
+                .method_not_allowed()
+                .with_header(String::from(ALLOW), allow_header(&allowed_methods));
+        }
+
+        if let Some(alternate) = toggle_trailing_slash(&segments) {
+            let found = self.find_handler(&http_request.method, &alternate);
+            match (inherited.trailing_slash, found) {
+                (TrailingSlash::Ignore, Some((target, route_params))) => {
+                    http_request.route_params.extend(route_params);
+                    http_request.matched_route =
+                        Some(mounted_pattern(route_prefix, &target.pattern));
+                    return (target.handler)(http_request);
+                }
+                (TrailingSlash::Redirect, Some(_)) => {
+                    return HttpResponse::default()
+                        .permanent_redirect(&redirect_location(&http_request));
+                }
+                _ => {}
             }
         }
+        match inherited.not_found_handler {
+            Some(not_found_handler) => not_found_handler(http_request),
+            None => HttpResponse::default().not_found(),
+        }
+    }
 
-        match node {
-            Some(node) if node.handler.is_some() => (node.handler.as_ref()).unwrap()(http_request),
-            _ => self.handle_not_found(http_request),
+    //True when a route for any method answers the raw path, in this router or a mounted one
+    fn has_route(&self, path: &str) -> bool {
+        let path = path.trim_start_matches('/');
+        if let Some((_, router, rest)) = self.find_mount(path) {
+            if router.has_route(&rest) {
+                return true;
+            }
         }
+        self.has_own_route(path)
     }
 
-    pub fn handle_not_found(&self, http_request: HttpRequest) -> HttpResponse {
-        if let Some(not_found_handler) = &self.not_found_handler {
-            not_found_handler(http_request)
-        } else {
-            HttpResponse::default().not_found()
+    fn has_own_route(&self, path: &str) -> bool {
+        let segments = decode_segments(path);
+        self.roots
+            .keys()
+            .any(|method| self.find(method, &segments).is_some())
+    }
+
+    //HEAD is answered by GET handlers, the body is dropped when the response is written
+    fn find_handler(
+        &self,
+        method: &HttpMethod,
+        segments: &[String],
+    ) -> Option<(&RouteTarget, HashMap<String, String>)> {
+        let found = self.find(method, segments);
+        if found.is_none() && *method == HttpMethod::HEAD {
+            return self.find(&HttpMethod::GET, segments);
+        }
+        found
+    }
+
+    //The longest mounted prefix wins, returns the prefix, the router and the raw path after the prefix
+    fn find_mount(&self, path: &str) -> Option<(&[String], &HttpRouter, String)> {
+        if self.mounts.is_empty() {
+            return None;
+        }
+        let parts: Vec<&str> = path.split('/').collect();
+        let decoded: Vec<String> = parts
+            .iter()
+            .map(|x| percent_decode(x).unwrap_or_else(|| String::from(*x)))
+            .collect();
+        let (prefix, router) = self
+            .mounts
+            .iter()
+            .filter(|(prefix, _)| starts_with_segments(&decoded, prefix))
+            .max_by_key(|(prefix, _)| prefix.len())?;
+        Some((prefix, router, parts[prefix.len()..].join("/")))
+    }
+
+    fn find(
+        &self,
+        method: &HttpMethod,
+        segments: &[String],
+    ) -> Option<(&RouteTarget, HashMap<String, String>)> {
+        let mut route_params = Vec::new();
+        let handler = self.roots.get(method)?.find(segments, &mut route_params)?;
+        let route_params = route_params
+            .into_iter()
+            .map(|(name, value)| (String::from(name), value))
+            .collect();
+        Some((handler, route_params))
+    }
+
+    fn allowed_methods(&self, segments: &[String]) -> Vec<HttpMethod> {
+        let methods = self
+            .roots
+            .keys()
+            .filter(|method| self.find(method, segments).is_some())
+            .cloned()
+            .collect();
+        with_automatic_methods(methods)
+    }
+
+    //Answered to "OPTIONS *", routes of mounted routers count as well
+    fn registered_methods(&self) -> Vec<HttpMethod> {
+        let mut methods: Vec<HttpMethod> = self.roots.keys().cloned().collect();
+        for (_, router) in &self.mounts {
+            for method in router.registered_methods() {
+                if !methods.contains(&method) {
+                    methods.push(method);
+                }
+            }
         }
+        with_automatic_methods(methods)
     }
 
     pub fn on_not_found(&mut self, not_found_handler: Arc<HttpRouteHandler>) {
         self.not_found_handler = Some(not_found_handler);
     }
+
+    //Renders errors returned by handlers and middlewares, mounted routers use it unless they set their own
+    pub fn on_error(&mut self, error_handler: Arc<HttpErrorHandler>) {
+        self.error_handler = Some(error_handler);
+    }
+}
+
+//Errors are rendered by the innermost router, the handler it uses already falls back to its parents'
+fn render_error(
+    mut response: HttpResponse,
+    error_handler: Option<&Arc<HttpErrorHandler>>,
+) -> HttpResponse {
+    let error = match response.error.take() {
+        Some(error) => error,
+        None => return response,
+    };
+    if error.status_code().is_server_error() {
+        eprintln!("Error handling request: {}", error);
+    }
+    match error_handler {
+        Some(error_handler) => error_handler(error),
+        None => response,
+    }
+}
+
+//Every path the route matches, "/files/{name}?" is both "/files" and "/files/{name}"
+fn route_variants(path: &str) -> Result<Vec<Vec<String>>, RouteError> {
+    let invalid = |reason: String| RouteError::InvalidPath {
+        path: String::from(path),
+        reason,
+    };
+    let parts: Vec<&str> = path.split('/').collect();
+    let required = parts
+        .iter()
+        .position(|x| is_optional_segment(x))
+        .unwrap_or(parts.len());
+    if parts[required..].iter().any(|x| !is_optional_segment(x)) {
+        return Err(invalid(String::from(
+            "optional path part that is not at the end",
+        )));
+    }
+    let parts: Vec<String> = parts
+        .iter()
+        .map(|x| match is_optional_segment(x) {
+            true => String::from(&x[..x.len() - 1]),
+            false => String::from(*x),
+        })
+        .collect();
+    for (i, part) in parts.iter().enumerate() {
+        if part.starts_with('*') && i != parts.len() - 1 {
+            return Err(invalid(String::from("'*' path part that is not the last")));
+        }
+        if let Some((var_name, constraint)) = parse_param_segment(part) {
+            if let Err(e) = ParamConstraint::parse(constraint) {
+                return Err(invalid(format!("constraint for {}: {}", var_name, e)));
+            }
+        }
+    }
+
+    let variants = (required..=parts.len())
+        .map(|length| match length {
+            //Without any of the optional parts "/files/{name}?" is "/files", not "/files/"
+            0 => vec![String::new()],
+            length => parts[..length].to_vec(),
+        })
+        .collect();
+    Ok(variants)
+}
+
+//Routes with the same shape match exactly the same paths
+fn route_shape(parts: &[String]) -> String {
+    let parts: Vec<String> = parts
+        .iter()
+        .map(|part| match parse_param_segment(part) {
+            _ if part.starts_with('*') => String::from("*"),
+            Some((_, constraint)) => format!("{{:{}}}", constraint),
+            None => part.clone(),
+        })
+        .collect();
+    parts.join("/")
+}
+
+//Fills the params of a route pattern, each segment is escaped on its own
+fn build_path(pattern: &str, params: &[(&str, &str)]) -> Option<String> {
+    let param = |name: &str| params.iter().find(|x| x.0 == name).map(|x| x.1);
+    let mut segments = Vec::new();
+    for part in pattern.split('/') {
+        let optional = is_optional_segment(part);
+        let part = if optional {
+            &part[..part.len() - 1]
+        } else {
+            part
+        };
+        if let Some(wildcard_name) = part.strip_prefix('*') {
+            let value = param(wildcard_name).filter(|_| !wildcard_name.is_empty())?;
+            let value: Vec<String> = value.split('/').map(percent_encode).collect();
+            segments.push(value.join("/"));
+        } else if let Some((var_name, constraint)) = parse_param_segment(part) {
+            let value = match param(var_name) {
+                Some(value) => value,
+                None if optional => break,
+                None => return None,
+            };
+            let constraint = ParamConstraint::parse(constraint).ok()?;
+            if value.is_empty() || !constraint.matches(value) {
+                return None;
+            }
+            segments.push(percent_encode(value));
+        } else {
+            segments.push(percent_encode(part));
+        }
+    }
+    Some(format!("/{}", segments.join("/")))
+}
+
+fn escape_html(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn is_optional_segment(path_part: &str) -> bool {
+    path_part.starts_with('{') && path_part.ends_with("}?")
+}
+
+//The segments with the trailing slash removed, or added if there was none
+fn toggle_trailing_slash(segments: &[String]) -> Option<Vec<String>> {
+    match segments.split_last() {
+        //The root path has no alternative
+        Some((last, [])) if last.is_empty() => None,
+        Some((last, rest)) if last.is_empty() => Some(rest.to_vec()),
+        Some(_) => {
+            let mut alternate = segments.to_vec();
+            alternate.push(String::new());
+            Some(alternate)
+        }
+        None => None,
+    }
+}
+
+//The full path the client sent, so it is also right for mounted routers
+fn redirect_location(http_request: &HttpRequest) -> String {
+    let raw_path = http_request.raw_path();
+    let mut location = match raw_path.strip_suffix('/') {
+        Some(without_slash) => String::from(without_slash),
+        None => format!("{}/", raw_path),
+    };
+    if let Some(query_string) = &http_request.query_string {
+        location.push('?');
+        location.push_str(query_string);
+    }
+    location
+}
+
+//A root route of a mounted router answers the prefix itself
+fn prefixed_variant(prefix: &[String], variant: Vec<String>) -> Vec<String> {
+    if variant == [""] && !prefix.is_empty() {
+        return prefix.to_vec();
+    }
+    prefix.iter().cloned().chain(variant).collect()
+}
+
+//Pattern with a leading slash of a route of the router mounted at the prefix, "" for the root router
+fn mounted_pattern(prefix: &str, pattern: &str) -> String {
+    //A root route of a mounted router answers the prefix itself
+    match pattern {
+        "" if !prefix.is_empty() => String::from(prefix),
+        pattern => format!("{}/{}", prefix, pattern),
+    }
+}
+
+fn prefixed_pattern(prefix: &[String], pattern: &str) -> String {
+    match (prefix.join("/").as_str(), pattern) {
+        ("", pattern) => String::from(pattern),
+        (prefix, "") => String::from(prefix),
+        (prefix, pattern) => format!("{}/{}", prefix, pattern),
+    }
+}
+
+//Shape relative to a router mounted at the prefix, None when it is outside of the prefix
+fn strip_shape_prefix(shape: &str, prefix: &[String]) -> Option<String> {
+    let prefix = prefix.join("/");
+    if prefix.is_empty() {
+        return Some(String::from(shape));
+    }
+    if shape == prefix {
+        return Some(String::new());
+    }
+    shape
+        .strip_prefix(&prefix)
+        .and_then(|x| x.strip_prefix('/'))
+        .map(String::from)
+}
+
+//Segments are decoded one by one since %2F is not a separator
+fn decode_segments(path: &str) -> Vec<String> {
+    path.split('/')
+        .map(|x| percent_decode(x).unwrap_or_else(|| String::from(x)))
+        .collect()
+}
+
+fn path_segments(path: &str) -> Vec<String> {
+    path.split('/')
+        .filter(|x| !x.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+fn starts_with_segments<T: AsRef<str>>(segments: &[T], prefix: &[String]) -> bool {
+    prefix.len() <= segments.len()
+        && prefix
+            .iter()
+            .zip(segments)
+            .all(|(a, b)| a.as_str() == b.as_ref())
+}
+
+//HEAD and OPTIONS are answered by the router even without explicit routes
+fn with_automatic_methods(mut methods: Vec<HttpMethod>) -> Vec<HttpMethod> {
+    if methods.is_empty() {
+        return methods;
+    }
+    if methods.contains(&HttpMethod::GET) && !methods.contains(&HttpMethod::HEAD) {
+        methods.push(HttpMethod::HEAD);
+    }
+    if !methods.contains(&HttpMethod::OPTIONS) {
+        methods.push(HttpMethod::OPTIONS);
+    }
+    methods
+}
+
+fn allow_header(methods: &[HttpMethod]) -> String {
+    let mut methods: Vec<&HttpMethod> = methods.iter().collect();
+    //Standard methods keep their usual order, extension ones go last
+    methods.sort_by_key(|method| {
+        let position = HttpMethod::STANDARD.iter().position(|x| x == *method);
+        (
+            position.unwrap_or(HttpMethod::STANDARD.len()),
+            method.to_string(),
+        )
+    });
+    let methods: Vec<&str> = methods.iter().map(|x| x.to_string()).collect();
+    methods.join(", ")
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::http::http_router::HttpRouter;
-    use crate::http::{HttpMethod, HttpRequest, HttpResponse, HttpVersion, StatusCode};
-    use std::collections::HashMap;
+    use crate::http::http_router::{HttpRouter, RouteError, TrailingSlash};
+    use crate::http::middleware::Next;
+    use crate::http::route_param::ParamError;
+    use crate::http::{HttpError, HttpMethod, HttpRequest, HttpResponse, StatusCode};
     use std::sync::Arc;
 
     fn test_http_request(method: HttpMethod, path: &str) -> HttpRequest {
-        HttpRequest {
-            method,
-            http_version: HttpVersion::_1_1,
-            path: String::from(path),
-            headers: HashMap::new(),
-            content: None,
-            route_params: HashMap::new(),
-        }
+        HttpRequest::new(method, String::from(path))
     }
 
     #[test]
@@ -182,7 +1082,9 @@ mod tests {
     fn it_calls_route_handler() {
         let mut router = HttpRouter::default();
         let on_handler = |_| HttpResponse::default().with_string_content("Called!");
-        router.on(HttpMethod::GET, "/hello", Arc::new(on_handler));
+        router
+            .on(HttpMethod::GET, "/hello", Arc::new(on_handler))
+            .unwrap();
         let response = router.handle(test_http_request(HttpMethod::GET, "/hello"));
         assert_eq!(response.content_as_string(), "Called!");
     }
@@ -191,7 +1093,9 @@ mod tests {
     fn it_calls_deeper_route_handler() {
         let mut router = HttpRouter::default();
         let on_handler = |_| HttpResponse::default().with_string_content("Called!");
-        router.on(HttpMethod::GET, "/hello/world", Arc::new(on_handler));
+        router
+            .on(HttpMethod::GET, "/hello/world", Arc::new(on_handler))
+            .unwrap();
         let response = router.handle(test_http_request(HttpMethod::GET, "/hello/world"));
         assert_eq!(response.content_as_string(), "Called!");
     }
@@ -201,39 +1105,759 @@ mod tests {
         let mut router = HttpRouter::default();
         let on_handler =
             |x: HttpRequest| HttpResponse::default().with_string_content(x.path.as_str());
-        router.on(HttpMethod::GET, "/static/*", Arc::new(on_handler));
+        router
+            .on(HttpMethod::GET, "/static/*", Arc::new(on_handler))
+            .unwrap();
         let response = router.handle(test_http_request(HttpMethod::GET, "/static/path/for/file"));
         assert_eq!(response.content_as_string(), "/static/path/for/file");
     }
 
+    #[test]
+    fn it_captures_named_catch_all() {
+        let mut router = HttpRouter::default();
+        router.get("/static/*rest", |x: HttpRequest| {
+            HttpResponse::default().with_string_content(x.route_params.get("rest").unwrap())
+        });
+        let response = router.handle(test_http_request(
+            HttpMethod::GET,
+            "/static/path/for/my%20file",
+        ));
+        assert_eq!(response.content_as_string(), "path/for/my file");
+    }
+
+    #[test]
+    fn it_matches_optional_segments() {
+        let mut router = HttpRouter::default();
+        router.get("/files/{name}?/{version:u32}?", |x: HttpRequest| {
+            let name = x.route_params.get("name").map_or("none", |x| x.as_str());
+            let version = x.route_params.get("version").map_or("none", |x| x.as_str());
+            HttpResponse::default().with_string_content(&format!("{} {}", name, version))
+        });
+        let response = router.handle(test_http_request(HttpMethod::GET, "/files"));
+        assert_eq!(response.content_as_string(), "none none");
+        let response = router.handle(test_http_request(HttpMethod::GET, "/files/a"));
+        assert_eq!(response.content_as_string(), "a none");
+        let response = router.handle(test_http_request(HttpMethod::GET, "/files/a/2"));
+        assert_eq!(response.content_as_string(), "a 2");
+        let response = router.handle(test_http_request(HttpMethod::GET, "/files/a/b"));
+        assert_eq!(response.status_code, StatusCode::_404);
+    }
+
+    #[test]
+    fn it_refuses_invalid_routes() {
+        let mut router = HttpRouter::default();
+        let on_handler = Arc::new(|_| HttpResponse::default());
+        for path in &["/files/{name}?/raw", "/static/*/raw", "/items/{slug:[a-z}"] {
+            let result = router.on(HttpMethod::GET, path, on_handler.clone());
+            assert!(matches!(result, Err(RouteError::InvalidPath { .. })));
+        }
+    }
+
+    #[test]
+    fn it_detects_route_conflicts() {
+        let mut router = HttpRouter::default();
+        let on_handler = Arc::new(|_| HttpResponse::default());
+        let mut on = |path| router.on(HttpMethod::GET, path, on_handler.clone());
+        assert_eq!(on("/a/?x"), Ok(()));
+        assert_eq!(
+            on("/a/?x"),
+            Err(RouteError::Duplicate {
+                method: HttpMethod::GET,
+                path: String::from("a/?x")
+            })
+        );
+        assert_eq!(
+            on("/a/{y}"),
+            Err(RouteError::Ambiguous {
+                method: HttpMethod::GET,
+                path: String::from("a/{y}"),
+                existing: String::from("a/?x")
+            })
+        );
+        assert_eq!(on("/a/{id:u64}"), Ok(()));
+        assert_eq!(on("/a/?x/b"), Ok(()));
+        assert_eq!(on("/files/{name}?"), Ok(()));
+        assert!(matches!(on("/files"), Err(RouteError::Ambiguous { .. })));
+        assert_eq!(on("/static/*rest"), Ok(()));
+        assert!(matches!(on("/static/*"), Err(RouteError::Ambiguous { .. })));
+        assert_eq!(
+            router.on(HttpMethod::POST, "/a/{y}", on_handler.clone()),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn it_detects_mount_conflicts() {
+        let on_handler = Arc::new(|_| HttpResponse::default());
+        let api = || {
+            let mut api = HttpRouter::default();
+            api.on(
+                HttpMethod::GET,
+                "/users/{id}",
+                Arc::new(|_| HttpResponse::default()),
+            )
+            .unwrap();
+            api
+        };
+        let mut router = HttpRouter::default();
+        router
+            .on(HttpMethod::GET, "/api/users/{user}", on_handler.clone())
+            .unwrap();
+        assert_eq!(
+            router.mount("/api", api()),
+            Err(RouteError::Ambiguous {
+                method: HttpMethod::GET,
+                path: String::from("api/users/{id}"),
+                existing: String::from("api/users/{user}")
+            })
+        );
+
+        let mut router = HttpRouter::default();
+        assert_eq!(router.mount("/api", api()), Ok(()));
+        assert_eq!(
+            router.mount("/api/", HttpRouter::default()),
+            Err(RouteError::DuplicateMount(String::from("api")))
+        );
+        assert_eq!(
+            router.on(HttpMethod::GET, "/api/users/{id}", on_handler.clone()),
+            Err(RouteError::Duplicate {
+                method: HttpMethod::GET,
+                path: String::from("api/users/{id}")
+            })
+        );
+        assert_eq!(
+            router.on(HttpMethod::POST, "/api/users/{id}", on_handler.clone()),
+            Ok(())
+        );
+        assert_eq!(
+            router.on(HttpMethod::GET, "/api/users/me", on_handler.clone()),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn it_overrides_mounted_routes_when_allowed() {
+        let mut router = HttpRouter::default();
+        router.set_allow_overrides(true);
+        router.get("/api/health", |_| {
+            HttpResponse::default().with_string_content("old")
+        });
+        router
+            .scope("/api", |r| {
+                r.get("/health", |_| {
+                    HttpResponse::default().with_string_content("scope")
+                });
+                r.get("/users", |_| {
+                    HttpResponse::default().with_string_content("users")
+                });
+            })
+            .unwrap();
+        let response = router.handle(test_http_request(HttpMethod::GET, "/api/health"));
+        assert_eq!(response.content_as_string(), "scope");
+        assert_eq!(router.routes().count(), 2);
+
+        router.get("/api/users", |_| {
+            HttpResponse::default().with_string_content("parent")
+        });
+        let response = router.handle(test_http_request(HttpMethod::GET, "/api/users"));
+        assert_eq!(response.content_as_string(), "parent");
+
+        router
+            .scope("/api", |r| {
+                r.get("/", |_| {
+                    HttpResponse::default().with_string_content("new scope")
+                });
+            })
+            .unwrap();
+        let response = router.handle(test_http_request(HttpMethod::GET, "/api"));
+        assert_eq!(response.content_as_string(), "new scope");
+        let response = router.handle(test_http_request(HttpMethod::GET, "/api/health"));
+        assert_eq!(response.status_code, StatusCode::_404);
+    }
+
+    #[test]
+    fn it_overrides_conflicting_routes_when_allowed() {
+        let mut router = HttpRouter::default();
+        router.set_allow_overrides(true);
+        router.get("/a/?x", |_| {
+            HttpResponse::default().with_string_content("old")
+        });
+        router.get("/a/{y}", |x: HttpRequest| {
+            HttpResponse::default().with_string_content(x.route_params.get("y").unwrap())
+        });
+        let response = router.handle(test_http_request(HttpMethod::GET, "/a/new"));
+        assert_eq!(response.content_as_string(), "new");
+
+        router.get("/files/{name}?", |_| {
+            HttpResponse::default().with_string_content("name")
+        });
+        router.get("/files", |_| {
+            HttpResponse::default().with_string_content("files")
+        });
+        let response = router.handle(test_http_request(HttpMethod::GET, "/files"));
+        assert_eq!(response.content_as_string(), "files");
+        let response = router.handle(test_http_request(HttpMethod::GET, "/files/a"));
+        assert_eq!(response.content_as_string(), "name");
+    }
+
+    #[test]
+    fn it_applies_trailing_slash_policy() {
+        let mut router = HttpRouter::default();
+        router.get("/users", |_| {
+            HttpResponse::default().with_string_content("users")
+        });
+        router
+            .scope("/api", |r| {
+                r.get("/items/", |_| {
+                    HttpResponse::default().with_string_content("items")
+                });
+            })
+            .unwrap();
+
+        let response = router.handle(test_http_request(HttpMethod::GET, "/users/"));
+        assert_eq!(response.status_code, StatusCode::_404);
+
+        router.set_trailing_slash(TrailingSlash::Ignore);
+        let response = router.handle(test_http_request(HttpMethod::GET, "/users/"));
+        assert_eq!(response.content_as_string(), "users");
+        let response = router.handle(test_http_request(HttpMethod::GET, "/api/items"));
+        assert_eq!(response.content_as_string(), "items");
+
+        router.set_trailing_slash(TrailingSlash::Redirect);
+        let response = router.handle(test_http_request(HttpMethod::GET, "/users/?page=2"));
+        assert_eq!(response.status_code, StatusCode::_308);
+        assert_eq!(response.headers.get("Location").unwrap(), "/users?page=2");
+        let response = router.handle(test_http_request(HttpMethod::GET, "/api/items"));
+        assert_eq!(response.headers.get("Location").unwrap(), "/api/items/");
+        let response = router.handle(test_http_request(HttpMethod::GET, "/missing/"));
+        assert_eq!(response.status_code, StatusCode::_404);
+    }
+
     #[test]
     fn it_calls_route_with_right_value() {
         let mut router = HttpRouter::default();
         let on_handler = |x: HttpRequest| {
             HttpResponse::default().with_string_content(x.route_params.get("key").unwrap())
         };
-        router.on(HttpMethod::GET, "/with_var/?key", Arc::new(on_handler));
+        router
+            .on(HttpMethod::GET, "/with_var/?key", Arc::new(on_handler))
+            .unwrap();
         let response = router.handle(test_http_request(HttpMethod::GET, "/with_var/expected"));
         assert_eq!(response.content_as_string(), "expected");
     }
 
+    #[test]
+    fn it_matches_multi_level_params() {
+        let mut router = HttpRouter::default();
+        router.get("/users/?id/posts/?post", |x: HttpRequest| {
+            let content = format!(
+                "{} {}",
+                x.route_params.get("id").unwrap(),
+                x.route_params.get("post").unwrap()
+            );
+            HttpResponse::default().with_string_content(&content)
+        });
+        let response = router.handle(test_http_request(HttpMethod::GET, "/users/5/posts/7"));
+        assert_eq!(response.content_as_string(), "5 7");
+        let response = router.handle(test_http_request(HttpMethod::GET, "/users/5/posts"));
+        assert_eq!(response.status_code, StatusCode::_404);
+    }
+
+    #[test]
+    fn it_prefers_static_then_param_then_wildcard() {
+        let mut router = HttpRouter::default();
+        router.get("/files/*", |_| {
+            HttpResponse::default().with_string_content("wildcard")
+        });
+        router.get("/files/?name", |_| {
+            HttpResponse::default().with_string_content("param")
+        });
+        router.get("/files/readme", |_| {
+            HttpResponse::default().with_string_content("static")
+        });
+
+        let response = router.handle(test_http_request(HttpMethod::GET, "/files/readme"));
+        assert_eq!(response.content_as_string(), "static");
+        let response = router.handle(test_http_request(HttpMethod::GET, "/files/other"));
+        assert_eq!(response.content_as_string(), "param");
+        let response = router.handle(test_http_request(HttpMethod::GET, "/files/a/b"));
+        assert_eq!(response.content_as_string(), "wildcard");
+    }
+
+    #[test]
+    fn it_backtracks_when_a_deeper_segment_fails() {
+        let mut router = HttpRouter::default();
+        router.get("/users/me/settings", |_| {
+            HttpResponse::default().with_string_content("settings")
+        });
+        router.get("/users/?id/posts", |x: HttpRequest| {
+            HttpResponse::default().with_string_content(x.route_params.get("id").unwrap())
+        });
+        router.get("/teams/?team/members", |_| {
+            HttpResponse::default().with_string_content("members")
+        });
+        router.get("/teams/?id/projects", |x: HttpRequest| {
+            //Params captured by the branch that failed are not kept
+            assert!(!x.route_params.contains_key("team"));
+            HttpResponse::default().with_string_content(x.route_params.get("id").unwrap())
+        });
+
+        let response = router.handle(test_http_request(HttpMethod::GET, "/users/me/posts"));
+        assert_eq!(response.content_as_string(), "me");
+        let response = router.handle(test_http_request(HttpMethod::GET, "/users/me/settings"));
+        assert_eq!(response.content_as_string(), "settings");
+        let response = router.handle(test_http_request(HttpMethod::GET, "/teams/3/members"));
+        assert_eq!(response.content_as_string(), "members");
+        let response = router.handle(test_http_request(HttpMethod::GET, "/teams/4/projects"));
+        assert_eq!(response.content_as_string(), "4");
+    }
+
+    #[test]
+    fn it_falls_through_constrained_params() {
+        let mut router = HttpRouter::default();
+        router.get("/items/{slug:[a-z-]+}", |x: HttpRequest| {
+            let content = format!("slug {}", x.route_params.get("slug").unwrap());
+            HttpResponse::default().with_string_content(&content)
+        });
+        router.get("/items/{id:u64}", |x: HttpRequest| {
+            let id: u64 = x.param("id").unwrap();
+            HttpResponse::default().with_string_content(&format!("id {}", id + 1))
+        });
+        router.get("/items/{key}", |_| {
+            HttpResponse::default().with_string_content("any")
+        });
+
+        let response = router.handle(test_http_request(HttpMethod::GET, "/items/41"));
+        assert_eq!(response.content_as_string(), "id 42");
+        let response = router.handle(test_http_request(HttpMethod::GET, "/items/my-item"));
+        assert_eq!(response.content_as_string(), "slug my-item");
+        let response = router.handle(test_http_request(HttpMethod::GET, "/items/My_Item"));
+        assert_eq!(response.content_as_string(), "any");
+    }
+
+    #[test]
+    fn it_returns_param_errors() {
+        let request = test_http_request(HttpMethod::GET, "/");
+        assert_eq!(
+            request.param::<u64>("id"),
+            Err(ParamError::Missing(String::from("id")))
+        );
+        let mut request = test_http_request(HttpMethod::GET, "/");
+        request
+            .route_params
+            .insert(String::from("id"), String::from("abc"));
+        assert_eq!(
+            request.param::<u64>("id"),
+            Err(ParamError::Invalid {
+                name: String::from("id"),
+                value: String::from("abc")
+            })
+        );
+    }
+
+    #[test]
+    fn it_decodes_route_params_and_ignores_query() {
+        let mut router = HttpRouter::default();
+        let on_handler = |x: HttpRequest| {
+            let content = format!(
+                "{} {}",
+                x.route_params.get("key").unwrap(),
+                x.query_params.get("q").unwrap()
+            );
+            HttpResponse::default().with_string_content(&content)
+        };
+        router
+            .on(HttpMethod::GET, "/my path/?key", Arc::new(on_handler))
+            .unwrap();
+        let response = router.handle(test_http_request(
+            HttpMethod::GET,
+            "/my%20path/a%2Fb?q=x%26y",
+        ));
+        assert_eq!(response.content_as_string(), "a/b x&y");
+    }
+
+    #[test]
+    fn it_runs_middleware_only_for_prefix() {
+        let mut router = HttpRouter::default();
+        let on_handler = |_| HttpResponse::default().with_string_content("Called!");
+        router
+            .on(HttpMethod::GET, "/api/users", Arc::new(on_handler))
+            .unwrap();
+        router
+            .on(HttpMethod::GET, "/apiary", Arc::new(on_handler))
+            .unwrap();
+        let deny = |_: HttpRequest, _: &Next| HttpResponse::default().forbidden();
+        router.middleware_for("/api", Arc::new(deny));
+
+        let response = router.handle(test_http_request(HttpMethod::GET, "/api/users"));
+        assert_eq!(response.status_code, StatusCode::_403);
+        let response = router.handle(test_http_request(HttpMethod::GET, "/api/missing"));
+        assert_eq!(response.status_code, StatusCode::_403);
+        let response = router.handle(test_http_request(HttpMethod::GET, "/apiary"));
+        assert_eq!(response.status_code, StatusCode::_200);
+    }
+
+    #[test]
+    fn it_routes_to_scopes_and_mounted_routers() {
+        let mut router = HttpRouter::default();
+        router.get("/users", |_| {
+            HttpResponse::default().with_string_content("root")
+        });
+        router
+            .scope("/api/v1", |r| {
+                r.get("/users", |_| {
+                    HttpResponse::default().with_string_content("v1")
+                });
+                r.get("/", |_| {
+                    HttpResponse::default().with_string_content("v1 index")
+                });
+            })
+            .unwrap();
+        let mut admin = HttpRouter::default();
+        admin.get("/users/?id", |x: HttpRequest| {
+            HttpResponse::default().with_string_content(x.route_params.get("id").unwrap())
+        });
+        router.mount("/admin", admin).unwrap();
+
+        let response = router.handle(test_http_request(HttpMethod::GET, "/users"));
+        assert_eq!(response.content_as_string(), "root");
+        let response = router.handle(test_http_request(HttpMethod::GET, "/api/v1/users?x=1"));
+        assert_eq!(response.content_as_string(), "v1");
+        let response = router.handle(test_http_request(HttpMethod::GET, "/api/v1"));
+        assert_eq!(response.content_as_string(), "v1 index");
+        let response = router.handle(test_http_request(HttpMethod::GET, "/admin/users/a%2Fb"));
+        assert_eq!(response.content_as_string(), "a/b");
+        let response = router.handle(test_http_request(HttpMethod::POST, "/admin/users/5"));
+        assert_eq!(response.status_code, StatusCode::_405);
+    }
+
+    #[test]
+    fn it_sets_the_matched_route_pattern() {
+        let matched_route =
+            |x: HttpRequest| HttpResponse::default().with_string_content(&x.matched_route.unwrap());
+        let mut router = HttpRouter::default();
+        router.get("/users/{id}", matched_route);
+        router.get("/", matched_route);
+        router
+            .scope("/api", |r| {
+                r.scope("/v1", |r| {
+                    r.get("/posts/{id}/", matched_route);
+                    r.get("/", matched_route);
+                })
+                .unwrap();
+            })
+            .unwrap();
+        router.set_trailing_slash(TrailingSlash::Ignore);
+
+        let matched = |path: &str| {
+            let response = router.handle(test_http_request(HttpMethod::GET, path));
+            response.content_as_string()
+        };
+        assert_eq!(matched("/users/5"), "/users/{id}");
+        assert_eq!(matched("/"), "/");
+        assert_eq!(matched("/api/v1/posts/5/"), "/api/v1/posts/{id}/");
+        assert_eq!(matched("/api/v1/posts/5"), "/api/v1/posts/{id}/");
+        assert_eq!(matched("/api/v1"), "/api/v1");
+    }
+
+    #[test]
+    fn it_falls_back_to_parent_routes_under_mounted_prefix() {
+        let mut router = HttpRouter::default();
+        router.get("/api/health", |_| {
+            HttpResponse::default().with_string_content("healthy")
+        });
+        router
+            .scope("/api", |r| {
+                r.get("/users", |_| {
+                    HttpResponse::default().with_string_content("users")
+                });
+            })
+            .unwrap();
+
+        let response = router.handle(test_http_request(HttpMethod::GET, "/api/health"));
+        assert_eq!(response.content_as_string(), "healthy");
+        let response = router.handle(test_http_request(HttpMethod::GET, "/api/users"));
+        assert_eq!(response.content_as_string(), "users");
+        let response = router.handle(test_http_request(HttpMethod::GET, "/api/missing"));
+        assert_eq!(response.status_code, StatusCode::_404);
+    }
+
+    #[test]
+    fn it_uses_scope_not_found_handler_and_middlewares() {
+        let mut router = HttpRouter::default();
+        router.on_not_found(Arc::new(|_| {
+            HttpResponse::default()
+                .with_string_content("root not found")
+                .not_found()
+        }));
+        router.get("/public", |_| HttpResponse::default());
+        router
+            .scope("/api", |r| {
+                r.middleware(Arc::new(|_: HttpRequest, _: &Next| {
+                    HttpResponse::default().unauthorized()
+                }));
+            })
+            .unwrap();
+        router
+            .scope("/docs", |r| {
+                r.on_not_found(Arc::new(|_| {
+                    HttpResponse::default()
+                        .with_string_content("docs not found")
+                        .not_found()
+                }));
+            })
+            .unwrap();
+        router.scope("/shop", |_| {}).unwrap();
+
+        let response = router.handle(test_http_request(HttpMethod::GET, "/api/anything"));
+        assert_eq!(response.status_code, StatusCode::_401);
+        let response = router.handle(test_http_request(HttpMethod::GET, "/public"));
+        assert_eq!(response.status_code, StatusCode::_200);
+        let response = router.handle(test_http_request(HttpMethod::GET, "/docs/missing"));
+        assert_eq!(response.content_as_string(), "docs not found");
+        let response = router.handle(test_http_request(HttpMethod::GET, "/shop/missing"));
+        assert_eq!(response.content_as_string(), "root not found");
+    }
+
+    #[test]
+    fn it_lists_routes_and_builds_urls() {
+        let mut router = HttpRouter::default();
+        let on_handler = Arc::new(|_| HttpResponse::default());
+        router
+            .on_named(
+                HttpMethod::GET,
+                "/users/{id:u64}/posts",
+                "user_posts",
+                on_handler.clone(),
+            )
+            .unwrap();
+        router
+            .on_named(
+                HttpMethod::GET,
+                "/files/{name}/*rest",
+                "files",
+                on_handler.clone(),
+            )
+            .unwrap();
+        router
+            .on_named(HttpMethod::GET, "/docs/{page}?", "docs", on_handler.clone())
+            .unwrap();
+        router.post("/users", |_| HttpResponse::default());
+        router
+            .scope("/admin", |r| {
+                r.on_named(HttpMethod::GET, "/", "admin_index", on_handler.clone())
+                    .unwrap();
+            })
+            .unwrap();
+        assert_eq!(
+            router.on_named(HttpMethod::PUT, "/other", "files", on_handler.clone()),
+            Err(RouteError::DuplicateName(String::from("files")))
+        );
+
+        let routes: Vec<(HttpMethod, String, Option<String>)> = router.routes().collect();
+        assert_eq!(
+            routes,
+            vec![
+                (
+                    HttpMethod::GET,
+                    String::from("/users/{id:u64}/posts"),
+                    Some(String::from("user_posts"))
+                ),
+                (
+                    HttpMethod::GET,
+                    String::from("/files/{name}/*rest"),
+                    Some(String::from("files"))
+                ),
+                (
+                    HttpMethod::GET,
+                    String::from("/docs/{page}?"),
+                    Some(String::from("docs"))
+                ),
+                (HttpMethod::POST, String::from("/users"), None),
+                (
+                    HttpMethod::GET,
+                    String::from("/admin"),
+                    Some(String::from("admin_index"))
+                ),
+            ]
+        );
+
+        assert_eq!(
+            router.url_for("user_posts", &[("id", "5")]).unwrap(),
+            "/users/5/posts"
+        );
+        assert!(router.url_for("user_posts", &[("id", "abc")]).is_none());
+        assert!(router.url_for("user_posts", &[]).is_none());
+        assert!(router.url_for("missing", &[]).is_none());
+        assert_eq!(
+            router
+                .url_for("files", &[("name", "a b"), ("rest", "x/y?z")])
+                .unwrap(),
+            "/files/a%20b/x/y%3Fz"
+        );
+        assert_eq!(router.url_for("admin_index", &[]).unwrap(), "/admin");
+        assert_eq!(router.url_for("docs", &[]).unwrap(), "/docs");
+        assert_eq!(router.url_for("docs", &[("page", "a")]).unwrap(), "/docs/a");
+    }
+
+    #[test]
+    fn it_serves_route_table() {
+        let mut router = HttpRouter::default();
+        router.get("/<script>", |_| HttpResponse::default());
+        router.serve_route_table("/_routes");
+        let response = router.handle(test_http_request(HttpMethod::GET, "/_routes"));
+        assert_eq!(response.status_code, StatusCode::_200);
+        let content = response.content_as_string();
+        assert!(content.contains("<tr><td>GET</td><td>/&lt;script&gt;</td><td></td></tr>"));
+        let response = router.handle(test_http_request(HttpMethod::POST, "/_routes"));
+        assert_eq!(response.status_code, StatusCode::_404);
+    }
+
+    #[test]
+    fn it_renders_handler_errors() {
+        let mut router = HttpRouter::default();
+        router.get(
+            "/users/{id}",
+            |x: HttpRequest| -> Result<HttpResponse, HttpError> {
+                let id: u64 = x.param("id")?;
+                match id {
+                    1 => Ok(HttpResponse::default().with_string_content("found")),
+                    _ => Err(HttpError::not_found("No such user")),
+                }
+            },
+        );
+        router
+            .scope("/api", |r| {
+                r.on_error(Arc::new(|e: HttpError| e.to_problem_json()));
+                r.get("/fail", |_| {
+                    Err::<HttpResponse, _>(HttpError::conflict("Taken"))
+                });
+            })
+            .unwrap();
+
+        let response = router.handle(test_http_request(HttpMethod::GET, "/users/1"));
+        assert_eq!(response.content_as_string(), "found");
+        let response = router.handle(test_http_request(HttpMethod::GET, "/users/2"));
+        assert_eq!(response.status_code, StatusCode::_404);
+        assert!(response.error.is_none());
+        assert_eq!(response.content_as_string(), "No such user");
+        let response = router.handle(test_http_request(HttpMethod::GET, "/users/abc"));
+        assert_eq!(response.status_code, StatusCode::_400);
+        let response = router.handle(test_http_request(HttpMethod::GET, "/api/fail"));
+        assert_eq!(response.status_code, StatusCode::_409);
+        assert_eq!(
+            response.headers.get("Content-Type").unwrap(),
+            "application/problem+json"
+        );
+
+        router.on_error(Arc::new(|e: HttpError| {
+            HttpResponse::default()
+                .with_string_content(&format!("Oops: {}", e.message()))
+                .with_status(e.status_code())
+        }));
+        let response = router.handle(test_http_request(HttpMethod::GET, "/users/2"));
+        assert_eq!(response.content_as_string(), "Oops: No such user");
+    }
+
     #[test]
     fn it_calls_route_handler_for_root() {
         let mut router = HttpRouter::default();
         let on_handler = |_| HttpResponse::default().with_string_content("Called for root!");
-        router.on(HttpMethod::GET, "/", Arc::new(on_handler));
+        router
+            .on(HttpMethod::GET, "/", Arc::new(on_handler))
+            .unwrap();
         let response = router.handle(test_http_request(HttpMethod::GET, "/"));
         assert_eq!(response.status_code, StatusCode::_200);
         assert_eq!(response.content_as_string(), "Called for root!");
     }
 
+    #[test]
+    fn it_answers_head_with_get_handler() {
+        let mut router = HttpRouter::default();
+        let on_handler = |_| HttpResponse::default().with_string_content("Called!");
+        router
+            .on(HttpMethod::GET, "/path", Arc::new(on_handler))
+            .unwrap();
+        let response = router.handle(test_http_request(HttpMethod::HEAD, "/path"));
+        assert_eq!(response.status_code, StatusCode::_200);
+    }
+
+    #[test]
+    fn it_answers_options_with_allowed_methods() {
+        let mut router = HttpRouter::default();
+        let on_handler = |_| HttpResponse::default();
+        router
+            .on(HttpMethod::GET, "/path", Arc::new(on_handler))
+            .unwrap();
+        router
+            .on(HttpMethod::PATCH, "/path", Arc::new(on_handler))
+            .unwrap();
+        router
+            .on(HttpMethod::DELETE, "/other", Arc::new(on_handler))
+            .unwrap();
+        let response = router.handle(test_http_request(HttpMethod::OPTIONS, "/path"));
+        assert_eq!(response.status_code, StatusCode::_200);
+        assert_eq!(
+            response.headers.get("Allow").unwrap(),
+            "GET, HEAD, OPTIONS, PATCH"
+        );
+
+        let response = router.handle(test_http_request(HttpMethod::OPTIONS, "*"));
+        assert_eq!(
+            response.headers.get("Allow").unwrap(),
+            "GET, HEAD, DELETE, OPTIONS, PATCH"
+        );
+
+        router
+            .scope("/api", |api| {
+                api.on(HttpMethod::PUT, "/items", Arc::new(on_handler))
+                    .unwrap();
+            })
+            .unwrap();
+        let response = router.handle(test_http_request(HttpMethod::OPTIONS, "*"));
+        assert_eq!(
+            response.headers.get("Allow").unwrap(),
+            "GET, HEAD, PUT, DELETE, OPTIONS, PATCH"
+        );
+    }
+
+    #[test]
+    fn it_answers_method_not_allowed() {
+        let mut router = HttpRouter::default();
+        let on_handler = |_| HttpResponse::default();
+        router
+            .on(HttpMethod::POST, "/path", Arc::new(on_handler))
+            .unwrap();
+        let response = router.handle(test_http_request(HttpMethod::PUT, "/path"));
+        assert_eq!(response.status_code, StatusCode::_405);
+        assert_eq!(response.headers.get("Allow").unwrap(), "POST, OPTIONS");
+    }
+
+    #[test]
+    fn it_routes_extension_methods() {
+        let mut router = HttpRouter::default();
+        let on_handler = |_| HttpResponse::default().with_string_content("Brewing");
+        let brew = HttpMethod::Extension(String::from("BREW"));
+        router
+            .on(brew.clone(), "/pot", Arc::new(on_handler))
+            .unwrap();
+        let response = router.handle(test_http_request(brew, "/pot"));
+        assert_eq!(response.content_as_string(), "Brewing");
+
+        let unknown = HttpMethod::Extension(String::from("WHEN"));
+        let response = router.handle(test_http_request(unknown, "/pot"));
+        assert_eq!(response.status_code, StatusCode::_501);
+    }
+
     #[test]
     fn it_calls_route_for_right_method() {
         let mut router = HttpRouter::default();
         let on_handler = |_| HttpResponse::default().with_string_content("Called!");
-        router.on(HttpMethod::POST, "/path", Arc::new(on_handler));
+        router
+            .on(HttpMethod::POST, "/path", Arc::new(on_handler))
+            .unwrap();
         let response = router.handle(test_http_request(HttpMethod::GET, "/path"));
-        assert_eq!(response.status_code, StatusCode::_404);
+        assert_eq!(response.status_code, StatusCode::_405);
 
         let response = router.handle(test_http_request(HttpMethod::POST, "/path"));
         assert_eq!(response.status_code, StatusCode::_200);
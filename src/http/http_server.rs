@@ -1,13 +1,26 @@
-use crate::http::file_server::FileServer;
-use crate::http::http_router::HttpRouter;
-use crate::http::{HttpMethod, HttpRequest, HttpResponse, HttpVersion};
+use crate::http::extract::require_content_type;
+use crate::http::file_server::{FileServer, FileServerOptions, FILE_PATH_PARAM};
+use crate::http::form::FormConfig;
+use crate::http::http_parser::{
+    read_body, read_request_head, BodyFraming, RequestParseError, DEFAULT_MAX_BODY_SIZE,
+};
+use crate::http::http_router::{into_route_handler, HttpRouter, TrailingSlash};
+#[cfg(feature = "json")]
+use crate::http::json::{is_json_request, JsonConfig};
+use crate::http::middleware::Middleware;
+use crate::http::multipart::MultipartConfig;
+use crate::http::request_body::{ConnectionReader, RequestBody};
+use crate::http::{
+    ConnectionInfo, Extensions, HttpContentType, HttpError, HttpMethod, HttpRequest, HttpResponse,
+    HttpVersion, IntoResponse,
+};
 use crossbeam::channel::unbounded;
 use crossbeam::channel::Sender;
-use std::collections::HashMap;
 use std::io;
-use std::io::{BufRead, Read};
-use std::io::{BufReader, Write};
-use std::net::{Shutdown, TcpListener, TcpStream};
+use std::io::{BufReader, BufWriter, Write};
+use std::mem;
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::panic::{self, AssertUnwindSafe};
 use std::sync::atomic::AtomicBool;
 use std::sync::atomic::Ordering::Relaxed;
 use std::sync::Arc;
@@ -19,21 +32,31 @@ pub struct HttpServer {
     port: u16,
     router: HttpRouter,
     threads_count: u8,
+    keep_alive_timeout: Duration,
+    max_requests_per_connection: usize,
+    max_body_size: usize,
+    state: Arc<Extensions>,
+    listener_addr: Option<SocketAddr>, //known once listening, the port might have been chosen by the OS
     pub should_turn_off: Arc<AtomicBool>,
 }
 
 impl HttpServer {
-    pub fn listen(self) {
+    pub fn listen(mut self) {
         let complete_listen_addr = format!("{}:{}", self.listen_addr, self.port);
         let listener = TcpListener::bind(complete_listen_addr.as_str()).unwrap();
+        self.listener_addr = listener.local_addr().ok();
 
         let should_turn_off = self.should_turn_off.clone();
-        let sender: Sender<TcpStream> = HttpServer::launch_threads(Arc::new(self));
+        let sender: Sender<(TcpStream, u64)> = HttpServer::launch_threads(Arc::new(self));
         println!("Listening on {}", complete_listen_addr);
         listener.set_nonblocking(true).unwrap();
+        let mut connection_id = 0;
         for stream in listener.incoming() {
             match stream {
-                Ok(unwrapped_stream) => sender.send(unwrapped_stream).unwrap(),
+                Ok(unwrapped_stream) => {
+                    connection_id += 1;
+                    sender.send((unwrapped_stream, connection_id)).unwrap()
+                }
                 Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
                     thread::sleep(Duration::from_millis(5))
                 }
@@ -60,7 +83,7 @@ impl HttpServer {
         should_turn_off.store(true, Relaxed)
     }
 
-    fn launch_threads(self_ref: Arc<HttpServer>) -> Sender<TcpStream> {
+    fn launch_threads(self_ref: Arc<HttpServer>) -> Sender<(TcpStream, u64)> {
         let (s, r) = unbounded();
 
         for _ in 0..self_ref.threads_count {
@@ -69,8 +92,14 @@ impl HttpServer {
             thread::spawn(move || {
                 //println!("Connection established!");
                 loop {
-                    if let Ok(stream) = r.recv_timeout(Duration::from_secs(60 * 60 * 24)) {
-                        local_ref.process_message(stream)
+                    if let Ok((stream, id)) = r.recv_timeout(Duration::from_secs(60 * 60 * 24)) {
+                        //Handler panics are answered with 500 already, this keeps the worker alive if writing the response panics
+                        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+                            local_ref.process_connection(stream, id)
+                        }));
+                        if result.is_err() {
+                            eprintln!("Connection {} closed after a panic", id);
+                        }
                     }
                 }
             });
@@ -84,148 +113,437 @@ impl HttpServer {
             port,
             router: HttpRouter::default(),
             threads_count,
+            keep_alive_timeout: Duration::from_secs(5),
+            max_requests_per_connection: 100,
+            max_body_size: DEFAULT_MAX_BODY_SIZE,
+            state: Arc::new(Extensions::new()),
+            listener_addr: None,
             should_turn_off: Arc::new(AtomicBool::new(false)),
         }
     }
 
-    pub fn get<T: Fn(HttpRequest) -> HttpResponse + Send + Sync + 'static>(
-        &mut self,
-        path: &str,
-        handler: T,
-    ) {
-        self.router.on(HttpMethod::GET, path, Arc::new(handler));
+    //Time an idle persistent connection is kept open waiting for the next request
+    pub fn set_keep_alive_timeout(&mut self, keep_alive_timeout: Duration) {
+        self.keep_alive_timeout = keep_alive_timeout;
     }
 
-    pub fn post<T: Fn(HttpRequest) -> HttpResponse + Send + Sync + 'static>(
-        &mut self,
-        path: &str,
-        handler: T,
-    ) {
-        self.router.on(HttpMethod::POST, path, Arc::new(handler));
+    //After this many requests the connection is closed, even if the client asked to keep it alive
+    pub fn set_max_requests_per_connection(&mut self, max_requests_per_connection: usize) {
+        self.max_requests_per_connection = max_requests_per_connection;
     }
 
-    pub fn put<T: Fn(HttpRequest) -> HttpResponse + Send + Sync + 'static>(
-        &mut self,
-        path: &str,
-        handler: T,
-    ) {
-        self.router.on(HttpMethod::PUT, path, Arc::new(handler));
+    //Requests with a bigger body are answered with 413 without reading it, 8 MiB by default
+    //Multipart bodies are streamed to the handler and limited by MultipartConfig instead
+    pub fn set_max_body_size(&mut self, max_body_size: usize) {
+        self.max_body_size = max_body_size;
+    }
+
+    //Shared with every handler and middleware through the request, one value per type
+    pub fn with_state<T: Send + Sync + 'static>(&mut self, state: T) {
+        //Only the server holds the state until it starts listening
+        Arc::get_mut(&mut self.state)
+            .expect("State can't change while the server is listening")
+            .insert(state);
+    }
+
+    //Limits used by HttpRequest::form and the Form extractor
+    pub fn set_form_config(&mut self, config: FormConfig) {
+        self.with_state(config);
+    }
+
+    //Limits and temp directory used by HttpRequest::multipart
+    pub fn set_multipart_config(&mut self, config: MultipartConfig) {
+        self.with_state(config);
+    }
+
+    //JSON bodies bigger than this are refused with 413 before they are read, 1 MiB by default
+    #[cfg(feature = "json")]
+    pub fn set_json_limit(&mut self, limit: usize) {
+        self.with_state(JsonConfig { limit });
     }
 
-    pub fn delete<T: Fn(HttpRequest) -> HttpResponse + Send + Sync + 'static>(
+    //Whether "/users/" also answers "/users" and the other way around, strict by default
+    pub fn set_trailing_slash(&mut self, trailing_slash: TrailingSlash) {
+        self.router.set_trailing_slash(trailing_slash);
+    }
+
+    //Registering a route that conflicts with an existing one replaces it instead of panicking
+    pub fn set_allow_overrides(&mut self, allow_overrides: bool) {
+        self.router.set_allow_overrides(allow_overrides);
+    }
+
+    //Answers requests no route matches, instead of the default empty 404
+    pub fn on_not_found<T, R>(&mut self, handler: T)
+    where
+        T: Fn(HttpRequest) -> R + Send + Sync + 'static,
+        R: IntoResponse,
+    {
+        self.router.on_not_found(into_route_handler(handler));
+    }
+
+    //Turns errors returned by handlers into responses, e.g. on_error(|e| e.to_problem_json())
+    pub fn on_error<T: Fn(HttpError) -> HttpResponse + Send + Sync + 'static>(
         &mut self,
-        path: &str,
         handler: T,
     ) {
-        self.router.on(HttpMethod::DELETE, path, Arc::new(handler));
+        self.router.on_error(Arc::new(handler));
     }
 
-    pub fn serve_files(&mut self, path: &str, base_folder: &str) {
-        let (append, base_path) = match path {
-            path if path.ends_with("/*") => ("", &path[..path.len() - 2]),
-            path if path.ends_with('/') => ("", &path[..path.len() - 1]),
-            _ => ("/*", path),
-        };
+    //Runs around every request, in registration order
+    pub fn middleware<M: Middleware + 'static>(&mut self, middleware: M) {
+        self.router.middleware(Arc::new(middleware));
+    }
 
-        let path = format!("{}{}", path, append);
-        let file_server = FileServer::new(String::from(base_path), String::from(base_folder));
-        let handler = move |request| file_server.handle(request);
+    //Runs around every request under the path prefix, e.g. "/admin"
+    pub fn middleware_for<M: Middleware + 'static>(&mut self, prefix: &str, middleware: M) {
+        self.router.middleware_for(prefix, Arc::new(middleware));
+    }
+
+    //Groups routes under a path prefix, the scope gets its own middlewares and not found handler
+    //Panics like get when a route of the scope conflicts with an existing one
+    pub fn scope<F: FnOnce(&mut HttpRouter)>(&mut self, prefix: &str, build: F) {
+        if let Err(e) = self.router.scope(prefix, build) {
+            panic!("{}", e);
+        }
+    }
+
+    //Mounts a router built elsewhere, its routes are relative to the prefix
+    pub fn mount(&mut self, prefix: &str, router: HttpRouter) {
+        if let Err(e) = self.router.mount(prefix, router) {
+            panic!("{}", e);
+        }
+    }
+
+    pub fn get<T, R>(&mut self, path: &str, handler: T)
+    where
+        T: Fn(HttpRequest) -> R + Send + Sync + 'static,
+        R: IntoResponse,
+    {
         self.router
-            .on(HttpMethod::GET, path.as_str(), Arc::new(handler));
+            .on_or_panic(HttpMethod::GET, path, into_route_handler(handler));
     }
 
-    fn process_message(&self, mut stream: TcpStream) {
-        let mut reader = BufReader::new(&stream);
-        let mut line = String::new();
-        reader.read_line(&mut line).unwrap();
+    pub fn post<T, R>(&mut self, path: &str, handler: T)
+    where
+        T: Fn(HttpRequest) -> R + Send + Sync + 'static,
+        R: IntoResponse,
+    {
+        self.router
+            .on_or_panic(HttpMethod::POST, path, into_route_handler(handler));
+    }
 
-        //println!("First line: {}", line)
-        let splits: Vec<&str> = line.split(' ').collect();
-        if splits.len() != 3 {
-            eprintln!(
-                "First line of request had wrong splits size {}",
-                splits.len()
-            );
+    pub fn put<T, R>(&mut self, path: &str, handler: T)
+    where
+        T: Fn(HttpRequest) -> R + Send + Sync + 'static,
+        R: IntoResponse,
+    {
+        self.router
+            .on_or_panic(HttpMethod::PUT, path, into_route_handler(handler));
+    }
 
-            return;
+    pub fn delete<T, R>(&mut self, path: &str, handler: T)
+    where
+        T: Fn(HttpRequest) -> R + Send + Sync + 'static,
+        R: IntoResponse,
+    {
+        self.router
+            .on_or_panic(HttpMethod::DELETE, path, into_route_handler(handler));
+    }
+
+    pub fn patch<T, R>(&mut self, path: &str, handler: T)
+    where
+        T: Fn(HttpRequest) -> R + Send + Sync + 'static,
+        R: IntoResponse,
+    {
+        self.router
+            .on_or_panic(HttpMethod::PATCH, path, into_route_handler(handler));
+    }
+
+    //Named routes can be found in the route table and turned back into paths with url_for
+    pub fn named<T, R>(&mut self, method: HttpMethod, path: &str, name: &str, handler: T)
+    where
+        T: Fn(HttpRequest) -> R + Send + Sync + 'static,
+        R: IntoResponse,
+    {
+        let handler = into_route_handler(handler);
+        if let Err(e) = self.router.on_named(method, path, name, handler) {
+            panic!("{}", e);
+        }
+    }
+
+    //Builds the path of a named route, see HttpRouter::url_for
+    pub fn url_for(&self, name: &str, params: &[(&str, &str)]) -> Option<String> {
+        self.router.url_for(name, params)
+    }
+
+    //Lists every route as an HTML page, useful while developing
+    pub fn serve_route_table(&mut self, path: &str) {
+        self.router.serve_route_table(path);
+    }
+
+    //GET routes already answer HEAD requests, this is only needed to handle them differently
+    pub fn head<T, R>(&mut self, path: &str, handler: T)
+    where
+        T: Fn(HttpRequest) -> R + Send + Sync + 'static,
+        R: IntoResponse,
+    {
+        self.router
+            .on_or_panic(HttpMethod::HEAD, path, into_route_handler(handler));
+    }
+
+    //Replaces the automatic OPTIONS response for this path
+    pub fn options<T, R>(&mut self, path: &str, handler: T)
+    where
+        T: Fn(HttpRequest) -> R + Send + Sync + 'static,
+        R: IntoResponse,
+    {
+        self.router
+            .on_or_panic(HttpMethod::OPTIONS, path, into_route_handler(handler));
+    }
+
+    //Registers the handler for every standard method, extension methods need an explicit route
+    pub fn any<T, R>(&mut self, path: &str, handler: T)
+    where
+        T: Fn(HttpRequest) -> R + Send + Sync + 'static,
+        R: IntoResponse,
+    {
+        let handler = into_route_handler(handler);
+        for method in HttpMethod::STANDARD.iter() {
+            self.router
+                .on_or_panic(method.clone(), path, handler.clone());
         }
+    }
 
-        //TODO ignoring protocol version for now
+    pub fn serve_files(&mut self, path: &str, base_folder: &str) {
+        self.serve_files_with_options(path, base_folder, FileServerOptions::default());
+    }
 
-        let http_version = splits[2].trim();
-        let mut http_request = HttpRequest {
-            method: HttpMethod::from_method_string(splits[0]),
-            path: String::from(splits[1]),
-            http_version: HttpVersion::from_str(http_version),
-            headers: HashMap::new(),
-            content: None,
-            route_params: HashMap::new(),
+    pub fn serve_files_with_options(
+        &mut self,
+        path: &str,
+        base_folder: &str,
+        options: FileServerOptions,
+    ) {
+        let base_path = match path {
+            path if path.ends_with("/*") => &path[..path.len() - 2],
+            path if path.ends_with('/') => &path[..path.len() - 1],
+            _ => path,
         };
 
-        let mut content_length: Option<usize> = None;
-        //Read headers until we find a new line
-        loop {
-            let mut line = String::new();
-            reader.read_line(&mut line).unwrap();
+        let path = format!("{}/*{}", base_path, FILE_PATH_PARAM);
+        let file_server =
+            FileServer::with_options(String::from(base_path), String::from(base_folder), options);
+        let handler = move |request| file_server.handle(request);
+        self.router
+            .on_or_panic(HttpMethod::GET, path.as_str(), Arc::new(handler));
+    }
 
-            if line == "\r\n" {
-                break;
+    fn process_connection(&self, stream: TcpStream, connection_id: u64) {
+        //Accepted streams must block, the idle timeout is enforced through the read timeout
+        if stream.set_nonblocking(false).is_err()
+            || stream
+                .set_read_timeout(Some(self.keep_alive_timeout))
+                .is_err()
+        {
+            eprintln!("Unable to configure connection");
+            return;
+        }
+        let (peer_addr, local_addr) = match (stream.peer_addr(), stream.local_addr()) {
+            (Ok(peer_addr), Ok(local_addr)) => (peer_addr, local_addr),
+            _ => {
+                eprintln!("Unable to get connection addresses");
+                return;
             }
+        };
+        let mut connection = ConnectionInfo {
+            id: connection_id,
+            peer_addr,
+            local_addr,
+            listener_addr: self.listener_addr.unwrap_or(local_addr),
+            request_number: 0,
+        };
 
-            let splits: Vec<&str> = line.split(": ").collect();
-            if splits.len() != 2 {
-                eprintln!("Header had wrong splits size {}", line.len());
+        //The reader is kept for the whole connection so pipelined requests already buffered are not lost
+        let mut reader: ConnectionReader = match stream.try_clone() {
+            Ok(read_stream) => Box::new(BufReader::new(read_stream)),
+            Err(_) => {
+                eprintln!("Unable to configure connection");
                 return;
             }
-            let key = String::from(splits[0]);
-            let val = trim(splits[1]);
+        };
+        let mut writer = &stream;
+        let mut requests_served = 0;
+        loop {
+            let (mut http_request, framing) = match read_request_head(&mut reader) {
+                Ok(Some(head)) => head,
+                Ok(None) => break,
+                Err(e) => {
+                    self.handle_parse_error(&mut writer, e);
+                    break;
+                }
+            };
+            //Multipart bodies are read by the handler while it parses them, MultipartConfig limits them instead of max_body_size
+            let is_multipart =
+                require_content_type(&http_request, &HttpContentType::MULTIPART_FORM_DATA).is_ok();
+            let body_stream = if is_multipart && framing != BodyFraming::None {
+                let connection = mem::replace(&mut reader, Box::new(io::empty()));
+                let body_stream = RequestBody::new(connection, framing);
+                http_request.body_stream = Some(body_stream.clone());
+                Some(body_stream)
+            } else {
+                let max_body_size = self.max_body_size_for(&http_request);
+                if let Err(e) = read_body(&mut reader, &mut http_request, framing, max_body_size) {
+                    self.handle_parse_error(&mut writer, e);
+                    break;
+                }
+                None
+            };
+            requests_served += 1;
+            http_request.state = self.state.clone();
+            connection.request_number = requests_served;
+            http_request.connection = Some(connection);
 
-            if key == "Content-Length" {
-                content_length = Some(val.parse().unwrap())
-            }
+            let keep_alive = http_request.wants_keep_alive()
+                && requests_served < self.max_requests_per_connection
+                && !self.should_turn_off.load(Relaxed);
+            let http_version = http_request.http_version;
+            let accepts_trailers = http_request.accepts_trailers();
+            let write_body = http_request.method != HttpMethod::HEAD;
 
-            http_request.headers.insert(key, val);
-        }
+            //The connection is closed after a panic since the handler might have left the body half read
+            let handled =
+                panic::catch_unwind(AssertUnwindSafe(|| self.router.handle(http_request)));
+            let (mut response, keep_alive) = match handled {
+                Ok(response) => (response, keep_alive),
+                Err(_) => {
+                    eprintln!("Handler panicked on connection {}", connection_id);
+                    (HttpError::internal("Handler panicked").to_response(), false)
+                }
+            };
+            //Whatever the handler didn't read has to be skipped before the next request can be read
+            let keep_alive = match body_stream {
+                Some(body_stream) if keep_alive => match body_stream.finish(self.max_body_size) {
+                    Some(connection) => {
+                        reader = connection;
+                        keep_alive
+                    }
+                    None => false,
+                },
+                _ => keep_alive,
+            };
+            //Clients that didn't ask for trailers might not be able to parse them
+            if !accepts_trailers {
+                response.trailers.clear();
+            }
+            let keep_alive = keep_alive
+                && (http_version == HttpVersion::_1_1
+                    || response.content.known_length().is_some()
+                    || !response.status_code.allows_body());
+            if write_response(&mut writer, http_version, response, keep_alive, write_body).is_err()
+            {
+                eprintln!("Error writing response");
+                break;
+            }
 
-        if let Some(size) = content_length {
-            let mut buffer: Vec<u8> = vec![0; size];
-            reader.read_exact(buffer.as_mut_slice()).unwrap();
-            http_request.content = Some(buffer);
+            if !keep_alive {
+                break;
+            }
         }
+        let _ = stream.shutdown(Shutdown::Both);
+    }
 
-        let response = self.router.handle(http_request);
-        let mut response_builder = String::new();
-        response_builder.push_str(
-            format!(
-                "{} {} {}\r\n",
-                http_version,
-                response.status_code.to_code(),
-                response.status_code.to_string()
-            )
-            .as_str(),
-        );
-        stream.write_all(response_builder.as_bytes()).unwrap();
-
-        for header in &response.headers {
-            stream
-                .write_all(format!("{}: {}\r\n", header.0, header.1).as_bytes())
-                .unwrap();
-        }
-        if let Some(content) = response.content.as_deref() {
-            stream
-                .write_all(format!("Content-Length: {}\r\n", content.len()).as_bytes())
-                .unwrap();
+    //JSON bodies are only parsed up to the JSON limit, so there is no point in buffering more
+    #[cfg_attr(not(feature = "json"), allow(unused_variables))]
+    fn max_body_size_for(&self, http_request: &HttpRequest) -> usize {
+        #[cfg(feature = "json")]
+        if is_json_request(http_request) {
+            let config = self.state.get::<JsonConfig>().copied().unwrap_or_default();
+            return self.max_body_size.min(config.limit);
         }
-        stream.write_all(b"\r\n").unwrap();
+        self.max_body_size
+    }
 
-        if let Some(content_bytes) = response.content.as_deref() {
-            stream.write_all(content_bytes).unwrap();
+    //The connection is always closed after an invalid request since we can't know where the next one starts
+    fn handle_parse_error<W: Write>(&self, writer: &mut W, error: RequestParseError) {
+        let status_code = match error.status_code() {
+            Some(status_code) => status_code,
+            None => return,
+        };
+        eprintln!("Invalid request: {}", error);
+        let response = HttpResponse::default()
+            .with_string_content(status_code.to_string())
+            .with_status(status_code);
+        if write_response(writer, HttpVersion::_1_1, response, false, true).is_err() {
+            eprintln!("Error writing response");
         }
-        stream.shutdown(Shutdown::Both).unwrap();
     }
 }
 
-fn trim(original: &str) -> String {
-    let end = original.len() - 2;
-    String::from(&original[..end])
+fn write_response<W: Write>(
+    stream: &mut W,
+    http_version: HttpVersion,
+    response: HttpResponse,
+    keep_alive: bool,
+    write_body: bool,
+) -> io::Result<()> {
+    //Everything goes through a buffer so pipelined responses and small chunks don't get split into many small packets
+    let mut writer = BufWriter::new(stream);
+    write!(
+        writer,
+        "{} {} {}\r\n",
+        http_version.to_string(),
+        response.status_code.to_code(),
+        response.status_code.to_string()
+    )?;
+
+    for header in response.headers.iter() {
+        write!(writer, "{}: {}\r\n", header.0, header.1)?;
+    }
+    //1xx, 204 and 304 responses have no body, so they also get no framing headers
+    let allows_body = response.status_code.allows_body();
+    let content_length = response.content.known_length();
+    let chunked = allows_body && content_length.is_none() && http_version == HttpVersion::_1_1;
+    match content_length {
+        _ if !allows_body => {}
+        Some(length) => write!(writer, "Content-Length: {}\r\n", length)?,
+        None if chunked => writer.write_all(b"Transfer-Encoding: chunked\r\n")?,
+        //HTTP/1.0 clients don't know chunked, the end of the body is the end of the connection
+        None => {}
+    }
+    match (keep_alive, http_version) {
+        (false, _) => writer.write_all(b"Connection: close\r\n")?,
+        //HTTP/1.0 clients only keep the connection if we explicitly say so
+        (true, HttpVersion::_1_0) => writer.write_all(b"Connection: keep-alive\r\n")?,
+        (true, HttpVersion::_1_1) => {}
+    }
+    if chunked && !response.trailers.is_empty() {
+        write!(
+            writer,
+            "Trailer: {}\r\n",
+            response.trailers.names().join(", ")
+        )?;
+    }
+    writer.write_all(b"\r\n")?;
+
+    //Responses to HEAD carry the headers GET would send, but never a body
+    if !write_body || !allows_body {
+        return writer.flush();
+    }
+    if chunked {
+        response.content.for_each_chunk(|chunk| {
+            write!(writer, "{:X}\r\n", chunk.len())?;
+            writer.write_all(chunk)?;
+            writer.write_all(b"\r\n")
+        })?;
+        writer.write_all(b"0\r\n")?;
+        for trailer in response.trailers.iter() {
+            write!(writer, "{}: {}\r\n", trailer.0, trailer.1)?;
+        }
+        writer.write_all(b"\r\n")?;
+    } else {
+        response
+            .content
+            .for_each_chunk(|chunk| writer.write_all(chunk))?;
+    }
+    writer.flush()
 }
@@ -0,0 +1,181 @@
+use crate::http::header_map::{CONTENT_LENGTH, CONTENT_TYPE, TRANSFER_ENCODING};
+use crate::http::http_error::HttpError;
+use crate::http::{HeaderMap, HttpContentType, HttpResponse, StatusCode};
+
+//Anything a handler can return, it is turned into the response once the handler finishes
+pub trait IntoResponse {
+    fn into_response(self) -> HttpResponse;
+}
+
+impl IntoResponse for HttpResponse {
+    fn into_response(self) -> HttpResponse {
+        self
+    }
+}
+
+impl IntoResponse for &str {
+    fn into_response(self) -> HttpResponse {
+        HttpResponse::default().with_string_content(self)
+    }
+}
+
+impl IntoResponse for String {
+    fn into_response(self) -> HttpResponse {
+        HttpResponse::default().with_byte_content(self.into_bytes(), HttpContentType::TEXT_PLAIN)
+    }
+}
+
+impl IntoResponse for Vec<u8> {
+    fn into_response(self) -> HttpResponse {
+        HttpResponse::default().with_byte_content(self, HttpContentType::APPLICATION_OCTET_STREAM)
+    }
+}
+
+//Empty response with the status
+impl IntoResponse for StatusCode {
+    fn into_response(self) -> HttpResponse {
+        HttpResponse::default().with_status(self)
+    }
+}
+
+impl<B: IntoResponse> IntoResponse for (StatusCode, B) {
+    fn into_response(self) -> HttpResponse {
+        self.1.into_response().with_status(self.0)
+    }
+}
+
+//The headers replace the ones set by the body, e.g. a Content-Type given here wins
+//Content-Length and Transfer-Encoding are skipped, framing is decided when the response is written
+impl<B: IntoResponse> IntoResponse for (StatusCode, HeaderMap, B) {
+    fn into_response(self) -> HttpResponse {
+        let (status_code, headers, body) = self;
+        let mut response = body.into_response().with_status(status_code);
+        let is_framing = |name: &str| {
+            name.eq_ignore_ascii_case(CONTENT_LENGTH)
+                || name.eq_ignore_ascii_case(TRANSFER_ENCODING)
+        };
+        for name in headers.names().into_iter().filter(|x| !is_framing(x)) {
+            response.headers.remove(name);
+        }
+        for (name, value) in headers.iter().filter(|(name, _)| !is_framing(name)) {
+            response.headers.append(name, value);
+        }
+        if let Some(content_type) = headers.get(CONTENT_TYPE) {
+            response.content_type = HttpContentType::parse(content_type);
+        }
+        response
+    }
+}
+
+//None is answered with 404
+impl<T: IntoResponse> IntoResponse for Option<T> {
+    fn into_response(self) -> HttpResponse {
+        match self {
+            Some(value) => value.into_response(),
+            None => HttpResponse::default().not_found(),
+        }
+    }
+}
+
+//JSON that is already serialized, sent as application/json
+pub struct RawJson<T: Into<String>>(pub T);
+
+impl<T: Into<String>> IntoResponse for RawJson<T> {
+    fn into_response(self) -> HttpResponse {
+        let content: String = self.0.into();
+        HttpResponse::default()
+            .with_byte_content(content.into_bytes(), HttpContentType::APPLICATION_JSON)
+    }
+}
+
+//The error is kept in the response so the router's error handler can render it
+impl IntoResponse for HttpError {
+    fn into_response(self) -> HttpResponse {
+        let mut response = self.to_response();
+        response.error = Some(self);
+        response
+    }
+}
+
+impl<T: IntoResponse, E: IntoResponse> IntoResponse for Result<T, E> {
+    fn into_response(self) -> HttpResponse {
+        match self {
+            Ok(value) => value.into_response(),
+            Err(error) => error.into_response(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::http::into_response::IntoResponse;
+    use crate::http::RawJson;
+    use crate::http::{HeaderMap, HttpError, HttpResponse, StatusCode};
+
+    #[test]
+    fn it_converts_bodies() {
+        let response = "hello".into_response();
+        assert_eq!(
+            response.headers.get("Content-Type").unwrap(),
+            "text/plain; charset=utf-8"
+        );
+        assert_eq!(response.content_as_string(), "hello");
+
+        let response = vec![1u8, 2].into_response();
+        assert_eq!(
+            response.headers.get("Content-Type").unwrap(),
+            "application/octet-stream"
+        );
+
+        let response = RawJson("{\"a\":1}").into_response();
+        assert_eq!(
+            response.headers.get("Content-Type").unwrap(),
+            "application/json"
+        );
+        assert_eq!(response.content_as_string(), "{\"a\":1}");
+    }
+
+    #[test]
+    fn it_converts_status_and_headers() {
+        let response = StatusCode::_204.into_response();
+        assert_eq!(response.status_code, StatusCode::_204);
+
+        let response = (StatusCode::_201, String::from("created")).into_response();
+        assert_eq!(response.status_code, StatusCode::_201);
+        assert_eq!(response.content_as_string(), "created");
+
+        let mut headers = HeaderMap::new();
+        headers.append("Set-Cookie", "a=1");
+        headers.append("Set-Cookie", "b=2");
+        headers.insert("content-type", "text/html");
+        let response = (StatusCode::_202, headers, "<p>hi</p>").into_response();
+        assert_eq!(response.status_code, StatusCode::_202);
+        assert_eq!(response.headers.get_all("set-cookie").count(), 2);
+        assert_eq!(response.headers.get_all("Content-Type").count(), 1);
+        assert_eq!(response.content_type.unwrap().sub_type(), "html");
+
+        let mut headers = HeaderMap::new();
+        headers.insert("Content-Length", "999");
+        headers.insert("Transfer-Encoding", "chunked");
+        headers.insert("X-Kept", "yes");
+        let response = (StatusCode::_200, headers, "body").into_response();
+        assert!(!response.headers.contains_key("Content-Length"));
+        assert!(!response.headers.contains_key("Transfer-Encoding"));
+        assert_eq!(response.headers.get("X-Kept"), Some("yes"));
+    }
+
+    #[test]
+    fn it_converts_options_and_results() {
+        let response = None::<&str>.into_response();
+        assert_eq!(response.status_code, StatusCode::_404);
+        let response = Some("here").into_response();
+        assert_eq!(response.status_code, StatusCode::_200);
+
+        let result: Result<&str, HttpError> = Err(HttpError::forbidden("No"));
+        let response = result.into_response();
+        assert_eq!(response.status_code, StatusCode::_403);
+        assert!(response.error.is_some());
+        let result: Result<HttpResponse, StatusCode> = Err(StatusCode::_409);
+        assert_eq!(result.into_response().status_code, StatusCode::_409);
+    }
+}
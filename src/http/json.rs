@@ -0,0 +1,182 @@
+use crate::http::extract::FromRequest;
+use crate::http::header_map::CONTENT_TYPE;
+use crate::http::{HttpContentType, HttpError, HttpRequest, HttpResponse, IntoResponse};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::error::Category;
+
+pub const DEFAULT_JSON_LIMIT: usize = 1024 * 1024;
+
+//Read by HttpRequest::json from the server state, see HttpServer::set_json_limit
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct JsonConfig {
+    pub limit: usize, //bodies bigger than this are refused with 413 by the server before they are read
+}
+
+impl Default for JsonConfig {
+    fn default() -> Self {
+        JsonConfig {
+            limit: DEFAULT_JSON_LIMIT,
+        }
+    }
+}
+
+impl HttpResponse {
+    //A value that fails to serialize, e.g. a map with non string keys, is a 500
+    pub fn json<T: Serialize + ?Sized>(value: &T) -> HttpResponse {
+        match serde_json::to_vec(value) {
+            Ok(content) => HttpResponse::default()
+                .with_byte_content(content, HttpContentType::APPLICATION_JSON),
+            Err(error) => HttpError::internal(error).into_response(),
+        }
+    }
+}
+
+impl HttpRequest {
+    pub fn json<T: DeserializeOwned>(&self) -> Result<T, HttpError> {
+        let config = self.state::<JsonConfig>().copied().unwrap_or_default();
+        self.json_with_limit(config.limit)
+    }
+
+    //415 when the body is not declared as JSON, 400 for malformed JSON and 422 when it doesn't fit T.
+    //The server already refused bodies over the JsonConfig limit, so only a lower limit has an effect
+    pub fn json_with_limit<T: DeserializeOwned>(&self, limit: usize) -> Result<T, HttpError> {
+        if !is_json_request(self) {
+            return Err(HttpError::unsupported_media_type(
+                "Expected Content-Type application/json",
+            ));
+        }
+        let content = self.content.as_deref().unwrap_or_default();
+        if content.len() > limit {
+            return Err(HttpError::payload_too_large(&format!(
+                "JSON body is bigger than {} bytes",
+                limit
+            )));
+        }
+        serde_json::from_slice(content).map_err(|error| {
+            let message = error.to_string();
+            let http_error = match error.classify() {
+                Category::Data => HttpError::unprocessable_entity(&message),
+                _ => HttpError::bad_request(&message),
+            };
+            http_error.with_cause(error)
+        })
+    }
+}
+
+pub(crate) fn is_json_request(request: &HttpRequest) -> bool {
+    let content_type = request
+        .headers
+        .get(CONTENT_TYPE)
+        .and_then(HttpContentType::parse);
+    content_type.is_some_and(|x| is_json(&x))
+}
+
+//application/json and structured syntax suffixes like application/merge-patch+json
+fn is_json(content_type: &HttpContentType) -> bool {
+    content_type.main_type() == "application"
+        && (content_type.sub_type() == "json" || content_type.sub_type().ends_with("+json"))
+}
+
+//Body extractor when used as a handler argument, JSON response when returned from a handler
+pub struct Json<T>(pub T);
+
+impl<T: DeserializeOwned> FromRequest for Json<T> {
+    fn from_request(request: &HttpRequest) -> Result<Self, HttpError> {
+        request.json().map(Json)
+    }
+}
+
+impl<T: Serialize> IntoResponse for Json<T> {
+    fn into_response(self) -> HttpResponse {
+        HttpResponse::json(&self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::http::extract::extract;
+    use crate::http::json::{Json, JsonConfig};
+    use crate::http::{Extensions, HttpMethod, HttpRequest, HttpResponse, StatusCode};
+    use serde::{Deserialize, Serialize};
+    use std::sync::Arc;
+
+    #[derive(Serialize, Deserialize)]
+    struct User {
+        name: String,
+        age: u8,
+    }
+
+    fn json_request(content_type: &str, content: &str) -> HttpRequest {
+        let mut request = HttpRequest::new(HttpMethod::POST, String::from("/users"));
+        request.headers.insert("Content-Type", content_type);
+        request.content = Some(content.as_bytes().to_vec());
+        request
+    }
+
+    #[test]
+    fn it_serializes_responses() {
+        let user = User {
+            name: String::from("Ana \"A\""),
+            age: 30,
+        };
+        let response = HttpResponse::json(&user);
+        assert_eq!(
+            response.headers.get("Content-Type").unwrap(),
+            "application/json"
+        );
+        assert_eq!(
+            response.content_as_string(),
+            "{\"name\":\"Ana \\\"A\\\"\",\"age\":30}"
+        );
+    }
+
+    #[test]
+    fn it_parses_requests() {
+        let request = json_request(
+            "application/json; charset=utf-8",
+            "{\"name\":\"Ana\",\"age\":30}",
+        );
+        let user: User = request.json().unwrap();
+        assert_eq!((user.name.as_str(), user.age), ("Ana", 30));
+
+        let request = json_request("text/plain", "{\"name\":\"Ana\",\"age\":30}");
+        let error = request.json::<User>().err().unwrap();
+        assert_eq!(error.status_code(), StatusCode::_415);
+
+        let request = json_request("application/json", "{\"name\":");
+        let error = request.json::<User>().err().unwrap();
+        assert_eq!(error.status_code(), StatusCode::_400);
+
+        let request = json_request("application/vnd.api+json", "{\"name\":\"Ana\",\"age\":300}");
+        let error = request.json::<User>().err().unwrap();
+        assert_eq!(error.status_code(), StatusCode::_422);
+    }
+
+    #[test]
+    fn it_limits_body_size() {
+        let mut state = Extensions::new();
+        state.insert(JsonConfig { limit: 10 });
+        let mut request = json_request("application/json", "{\"name\":\"Ana\",\"age\":30}");
+        request.state = Arc::new(state);
+        let error = request.json::<User>().err().unwrap();
+        assert_eq!(error.status_code(), StatusCode::_413);
+    }
+
+    #[test]
+    fn it_extracts_and_returns_json() {
+        let handler = extract(|Json(mut user): Json<User>| {
+            user.age += 1;
+            (StatusCode::_201, Json(user))
+        });
+        let response = handler(json_request(
+            "application/json",
+            "{\"name\":\"Ana\",\"age\":30}",
+        ));
+        assert_eq!(response.status_code, StatusCode::_201);
+        assert_eq!(
+            response.content_as_string(),
+            "{\"name\":\"Ana\",\"age\":31}"
+        );
+    }
+}
@@ -0,0 +1,380 @@
+use crate::http::header_map::{ORIGIN, VARY};
+use crate::http::{HttpMethod, HttpRequest, HttpResponse};
+use std::sync::Arc;
+use std::time::Instant;
+
+//Code that runs around handlers, it can change the request, the response or answer directly without calling next
+pub trait Middleware: Send + Sync {
+    fn handle(&self, request: HttpRequest, next: &Next) -> HttpResponse;
+}
+
+impl<F> Middleware for F
+where
+    F: Fn(HttpRequest, &Next) -> HttpResponse + Send + Sync,
+{
+    fn handle(&self, request: HttpRequest, next: &Next) -> HttpResponse {
+        self(request, next)
+    }
+}
+
+//The rest of the pipeline, running it calls the following middleware or, after the last one, the handler
+pub struct Next<'a> {
+    middlewares: &'a [Arc<dyn Middleware>],
+    endpoint: &'a dyn Fn(HttpRequest) -> HttpResponse,
+}
+
+impl<'a> Next<'a> {
+    pub fn run(&self, request: HttpRequest) -> HttpResponse {
+        match self.middlewares.split_first() {
+            Some((middleware, rest)) => {
+                let next = Next {
+                    middlewares: rest,
+                    endpoint: self.endpoint,
+                };
+                middleware.handle(request, &next)
+            }
+            None => (self.endpoint)(request),
+        }
+    }
+}
+
+//Middlewares run in the given order, the first one is the outermost
+pub fn run_middlewares(
+    middlewares: &[Arc<dyn Middleware>],
+    request: HttpRequest,
+    endpoint: &dyn Fn(HttpRequest) -> HttpResponse,
+) -> HttpResponse {
+    Next {
+        middlewares,
+        endpoint,
+    }
+    .run(request)
+}
+
+//Wraps a single route handler, these middlewares run after the global and group ones
+pub fn with_middleware<T: Fn(HttpRequest) -> HttpResponse + Send + Sync + 'static>(
+    middlewares: Vec<Arc<dyn Middleware>>,
+    handler: T,
+) -> impl Fn(HttpRequest) -> HttpResponse + Send + Sync + 'static {
+    move |request| run_middlewares(&middlewares, request, &handler)
+}
+
+//Prints client address, method, target, status and duration of every request
+pub struct RequestLogger;
+
+impl Middleware for RequestLogger {
+    fn handle(&self, request: HttpRequest, next: &Next) -> HttpResponse {
+        let start = Instant::now();
+        let method = request.method.clone();
+        let target = request.raw_target.clone();
+        let peer_addr = match request.peer_addr() {
+            Some(peer_addr) => peer_addr.to_string(),
+            None => String::from("-"),
+        };
+        let response = next.run(request);
+        println!(
+            "{} {} {} {} {}ms",
+            peer_addr,
+            method.to_string(),
+            target,
+            response.status_code.to_code(),
+            start.elapsed().as_millis()
+        );
+        response
+    }
+}
+
+//Adds headers to every response that doesn't set them already, e.g. security headers
+#[derive(Default)]
+pub struct DefaultHeaders {
+    headers: Vec<(String, String)>,
+}
+
+impl DefaultHeaders {
+    pub fn new() -> Self {
+        DefaultHeaders::default()
+    }
+
+    pub fn with_header(mut self, header_key: &str, header_val: &str) -> Self {
+        self.headers
+            .push((String::from(header_key), String::from(header_val)));
+        self
+    }
+}
+
+impl Middleware for DefaultHeaders {
+    fn handle(&self, request: HttpRequest, next: &Next) -> HttpResponse {
+        let mut response = next.run(request);
+        for (key, val) in &self.headers {
+            if !response.headers.contains_key(key) {
+                response.headers.append(key, val);
+            }
+        }
+        response
+    }
+}
+
+//Answers CORS preflight requests and adds the CORS headers to responses for allowed origins
+pub struct Cors {
+    allowed_origins: Vec<String>, //empty means any origin
+    allowed_methods: Vec<HttpMethod>,
+    allowed_headers: Vec<String>,
+    exposed_headers: Vec<String>,
+    allow_credentials: bool,
+    max_age: Option<u32>,
+}
+
+impl Default for Cors {
+    fn default() -> Self {
+        Cors {
+            allowed_origins: Vec::new(),
+            allowed_methods: vec![
+                HttpMethod::GET,
+                HttpMethod::HEAD,
+                HttpMethod::POST,
+                HttpMethod::PUT,
+                HttpMethod::DELETE,
+                HttpMethod::PATCH,
+            ],
+            allowed_headers: Vec::new(),
+            exposed_headers: Vec::new(),
+            allow_credentials: false,
+            max_age: None,
+        }
+    }
+}
+
+impl Cors {
+    pub fn new() -> Self {
+        Cors::default()
+    }
+
+    pub fn allow_origin(mut self, origin: &str) -> Self {
+        self.allowed_origins.push(String::from(origin));
+        self
+    }
+
+    pub fn allow_methods(mut self, methods: Vec<HttpMethod>) -> Self {
+        self.allowed_methods = methods;
+        self
+    }
+
+    pub fn allow_header(mut self, header_key: &str) -> Self {
+        self.allowed_headers.push(String::from(header_key));
+        self
+    }
+
+    pub fn expose_header(mut self, header_key: &str) -> Self {
+        self.exposed_headers.push(String::from(header_key));
+        self
+    }
+
+    //Only has an effect together with allow_origin, "*" can't be combined with credentials
+    pub fn allow_credentials(mut self) -> Self {
+        self.allow_credentials = true;
+        self
+    }
+
+    pub fn max_age(mut self, seconds: u32) -> Self {
+        self.max_age = Some(seconds);
+        self
+    }
+
+    fn is_allowed(&self, origin: &str) -> bool {
+        self.allowed_origins.is_empty() || self.allowed_origins.iter().any(|x| x == origin)
+    }
+
+    //Credentials are only allowed for origins listed explicitly, otherwise any site could read them
+    fn add_origin_headers(&self, response: &mut HttpResponse, origin: &str) {
+        if self.allowed_origins.is_empty() {
+            response.headers.insert("Access-Control-Allow-Origin", "*");
+            return;
+        }
+        response
+            .headers
+            .insert("Access-Control-Allow-Origin", origin);
+        response.headers.append(VARY, ORIGIN);
+        if self.allow_credentials {
+            response
+                .headers
+                .insert("Access-Control-Allow-Credentials", "true");
+        }
+    }
+}
+
+impl Middleware for Cors {
+    fn handle(&self, request: HttpRequest, next: &Next) -> HttpResponse {
+        let origin = match request.headers.get(ORIGIN) {
+            Some(origin) if self.is_allowed(origin) => String::from(origin),
+            _ => return next.run(request),
+        };
+
+        let is_preflight = request.method == HttpMethod::OPTIONS
+            && request
+                .headers
+                .contains_key("Access-Control-Request-Method");
+        if is_preflight {
+            let mut response = HttpResponse::default().no_content();
+            self.add_origin_headers(&mut response, &origin);
+            let methods: Vec<&str> = self.allowed_methods.iter().map(|x| x.to_string()).collect();
+            response
+                .headers
+                .insert("Access-Control-Allow-Methods", &methods.join(", "));
+            //Without a configured list every requested header is accepted
+            let allowed_headers = if self.allowed_headers.is_empty() {
+                request
+                    .headers
+                    .get("Access-Control-Request-Headers")
+                    .map(String::from)
+            } else {
+                Some(self.allowed_headers.join(", "))
+            };
+            if let Some(allowed_headers) = allowed_headers {
+                response
+                    .headers
+                    .insert("Access-Control-Allow-Headers", &allowed_headers);
+            }
+            if let Some(max_age) = self.max_age {
+                response
+                    .headers
+                    .insert("Access-Control-Max-Age", &max_age.to_string());
+            }
+            return response;
+        }
+
+        let mut response = next.run(request);
+        self.add_origin_headers(&mut response, &origin);
+        if !self.exposed_headers.is_empty() {
+            response.headers.insert(
+                "Access-Control-Expose-Headers",
+                &self.exposed_headers.join(", "),
+            );
+        }
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::http::middleware::{
+        run_middlewares, with_middleware, Cors, DefaultHeaders, Middleware, Next,
+    };
+    use crate::http::{HttpMethod, HttpRequest, HttpResponse, StatusCode};
+    use std::sync::{Arc, Mutex};
+
+    fn recording(name: &'static str, calls: Arc<Mutex<Vec<String>>>) -> Arc<dyn Middleware> {
+        Arc::new(move |request: HttpRequest, next: &Next| {
+            calls.lock().unwrap().push(format!("before {}", name));
+            let response = next.run(request);
+            calls.lock().unwrap().push(format!("after {}", name));
+            response
+        })
+    }
+
+    #[test]
+    fn it_runs_middlewares_in_order() {
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let middlewares = vec![recording("a", calls.clone()), recording("b", calls.clone())];
+        let handler_calls = calls.clone();
+        let handler = move |_| {
+            handler_calls.lock().unwrap().push(String::from("handler"));
+            HttpResponse::default()
+        };
+        let request = HttpRequest::new(HttpMethod::GET, String::from("/"));
+        run_middlewares(&middlewares, request, &handler);
+        assert_eq!(
+            *calls.lock().unwrap(),
+            vec!["before a", "before b", "handler", "after b", "after a"]
+        );
+    }
+
+    #[test]
+    fn it_short_circuits() {
+        let deny: Arc<dyn Middleware> =
+            Arc::new(|_: HttpRequest, _: &Next| HttpResponse::default().unauthorized());
+        let handler = with_middleware(vec![deny], |_| panic!("Handler should not be called"));
+        let response = handler(HttpRequest::new(HttpMethod::GET, String::from("/")));
+        assert_eq!(response.status_code, StatusCode::_401);
+    }
+
+    #[test]
+    fn it_adds_default_headers() {
+        let middlewares: Vec<Arc<dyn Middleware>> = vec![Arc::new(
+            DefaultHeaders::new()
+                .with_header("X-Frame-Options", "DENY")
+                .with_header("Cache-Control", "no-store"),
+        )];
+        let handler = |_| {
+            HttpResponse::default()
+                .with_header(String::from("Cache-Control"), String::from("max-age=60"))
+        };
+        let request = HttpRequest::new(HttpMethod::GET, String::from("/"));
+        let response = run_middlewares(&middlewares, request, &handler);
+        assert_eq!(response.headers.get("X-Frame-Options"), Some("DENY"));
+        assert_eq!(response.headers.get("Cache-Control"), Some("max-age=60"));
+    }
+
+    #[test]
+    fn it_answers_cors_preflight() {
+        let middlewares: Vec<Arc<dyn Middleware>> = vec![Arc::new(
+            Cors::new()
+                .allow_origin("https://example.com")
+                .allow_methods(vec![HttpMethod::GET, HttpMethod::POST])
+                .max_age(600),
+        )];
+        let handler = |_| HttpResponse::default().with_string_content("Called!");
+
+        let mut request = HttpRequest::new(HttpMethod::OPTIONS, String::from("/api"));
+        request.headers.append("Origin", "https://example.com");
+        request
+            .headers
+            .append("Access-Control-Request-Method", "POST");
+        request
+            .headers
+            .append("Access-Control-Request-Headers", "Content-Type");
+        let response = run_middlewares(&middlewares, request, &handler);
+        assert_eq!(response.status_code, StatusCode::_204);
+        let header = |x| response.headers.get(x);
+        assert_eq!(
+            header("Access-Control-Allow-Origin"),
+            Some("https://example.com")
+        );
+        assert_eq!(header("Access-Control-Allow-Methods"), Some("GET, POST"));
+        assert_eq!(header("Access-Control-Allow-Headers"), Some("Content-Type"));
+        assert_eq!(header("Access-Control-Max-Age"), Some("600"));
+
+        let mut request = HttpRequest::new(HttpMethod::GET, String::from("/api"));
+        request.headers.append("Origin", "https://evil.com");
+        let response = run_middlewares(&middlewares, request, &handler);
+        assert!(!response.headers.contains_key("Access-Control-Allow-Origin"));
+    }
+
+    #[test]
+    fn it_refuses_credentials_without_origin_list() {
+        let middlewares: Vec<Arc<dyn Middleware>> = vec![Arc::new(Cors::new().allow_credentials())];
+        let handler = |_| HttpResponse::default();
+        let mut request = HttpRequest::new(HttpMethod::GET, String::from("/api"));
+        request.headers.append("Origin", "https://evil.com");
+        let response = run_middlewares(&middlewares, request, &handler);
+        assert_eq!(
+            response.headers.get("Access-Control-Allow-Origin"),
+            Some("*")
+        );
+        assert!(!response
+            .headers
+            .contains_key("Access-Control-Allow-Credentials"));
+
+        let middlewares: Vec<Arc<dyn Middleware>> = vec![Arc::new(
+            Cors::new()
+                .allow_origin("https://example.com")
+                .allow_credentials(),
+        )];
+        let mut request = HttpRequest::new(HttpMethod::GET, String::from("/api"));
+        request.headers.append("Origin", "https://example.com");
+        let response = run_middlewares(&middlewares, request, &handler);
+        assert_eq!(
+            response.headers.get("Access-Control-Allow-Credentials"),
+            Some("true")
+        );
+    }
+}
@@ -1,79 +1,91 @@
-use crate::http::HttpContentType::TEXTPLAIN;
-use enum_iterator::IntoEnumIterator;
+use crate::http::http_body::HttpBody;
+use crate::http::route_param::ParamError;
+use crate::http::url_encoding::{percent_decode, UrlEncodedParams};
+use crossbeam::channel::Receiver;
 use std::collections::HashMap;
+use std::io::Read;
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::sync::Arc;
 
+pub mod connection_info;
+pub mod content_type;
+pub mod extensions;
+pub mod extract;
 pub mod file_server;
+pub mod form;
+pub mod header_map;
+pub mod http_body;
+pub mod http_error;
+pub mod http_parser;
 pub mod http_router;
 pub mod http_server;
+pub mod into_response;
+#[cfg(feature = "json")]
+pub mod json;
+pub mod middleware;
+pub mod multipart;
+pub mod request_body;
+pub mod route_param;
+pub mod status_code;
+pub mod url_encoding;
 
-#[derive(Debug, IntoEnumIterator, PartialEq, Eq, Hash)]
+pub use connection_info::ConnectionInfo;
+pub use content_type::HttpContentType;
+pub use extensions::Extensions;
+pub use header_map::HeaderMap;
+pub use http_error::HttpError;
+pub use into_response::{IntoResponse, RawJson};
+#[cfg(feature = "json")]
+pub use json::Json;
+pub use request_body::RequestBody;
+pub use status_code::StatusCode;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum HttpMethod {
     GET,
-    PUT,
+    HEAD,
     POST,
+    PUT,
     DELETE,
-    OPTION,
-}
-
-pub enum HttpContentType {
-    TEXTPLAIN,
-}
-
-impl HttpContentType {
-    fn to_string_with_encoding(&self) -> &str {
-        match self {
-            TEXTPLAIN => "text/plain; charset=utf-8",
-        }
-    }
+    CONNECT,
+    OPTIONS,
+    TRACE,
+    PATCH,
+    Extension(String), //any other method token, methods are case sensitive
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum HttpVersion {
+    _1_0,
     _1_1,
 }
 
 impl HttpVersion {
-    fn from_str(val: &str) -> HttpVersion {
+    fn from_str(val: &str) -> Option<HttpVersion> {
         match val {
-            "HTTP/1.1" => HttpVersion::_1_1,
-            _ => panic!("Http version not supported: {}", val),
+            "HTTP/1.0" => Some(HttpVersion::_1_0),
+            "HTTP/1.1" => Some(HttpVersion::_1_1),
+            _ => None,
         }
     }
 
     pub fn to_string(&self) -> &str {
         match self {
+            HttpVersion::_1_0 => "HTTP/1.0",
             HttpVersion::_1_1 => "HTTP/1.1",
         }
     }
 }
 
-#[derive(PartialEq, Eq, Debug)]
-pub enum StatusCode {
-    _404,
-    _200,
-}
-
-impl StatusCode {
-    pub fn to_string(&self) -> &str {
-        match self {
-            StatusCode::_404 => "Not Found",
-            StatusCode::_200 => "OK",
-        }
-    }
-
-    pub fn to_code(&self) -> u16 {
-        match self {
-            StatusCode::_404 => 404,
-            StatusCode::_200 => 200,
-        }
-    }
-}
-
 //TODO get better API to write a response
 pub struct HttpResponse {
     pub status_code: StatusCode,
     pub content_type: Option<HttpContentType>,
-    pub content: Option<Vec<u8>>,
-    pub headers: HashMap<String, String>,
+    pub content: HttpBody,
+    pub headers: HeaderMap,
+    pub trailers: HeaderMap, //trailers are only sent when the body is chunked
+    pub error: Option<HttpError>, //set for handler errors until the error handler renders them
 }
 
 impl Default for HttpResponse {
@@ -81,25 +93,64 @@ impl Default for HttpResponse {
         HttpResponse {
             status_code: StatusCode::_200,
             content_type: None,
-            content: None,
-            headers: HashMap::new(),
+            content: HttpBody::Empty,
+            headers: HeaderMap::new(),
+            trailers: HeaderMap::new(),
+            error: None,
         }
     }
 }
 
 impl HttpResponse {
     pub fn with_string_content(mut self, content: &str) -> HttpResponse {
-        self.content_type = Some(HttpContentType::TEXTPLAIN);
-        self.content = Some(content.as_bytes().to_vec());
-        self.with_header(
-            String::from("Content-Type"),
-            String::from(HttpContentType::TEXTPLAIN.to_string_with_encoding()),
-        )
+        self.content = HttpBody::Bytes(content.as_bytes().to_vec());
+        self.with_content_type(HttpContentType::TEXT_PLAIN)
     }
 
     pub fn with_byte_content(mut self, content: Vec<u8>, content_type: HttpContentType) -> Self {
-        self.content = Some(content);
+        self.content = HttpBody::Bytes(content);
+        self.with_content_type(content_type)
+    }
+
+    //Without a length the content is streamed with chunked encoding
+    pub fn with_reader_content<R: Read + Send + 'static>(
+        mut self,
+        content: R,
+        length: Option<u64>,
+        content_type: HttpContentType,
+    ) -> Self {
+        self.content = HttpBody::Reader(Box::new(content), length);
+        self.with_content_type(content_type)
+    }
+
+    pub fn with_chunked_content<I: Iterator<Item = Vec<u8>> + Send + 'static>(
+        mut self,
+        chunks: I,
+        content_type: HttpContentType,
+    ) -> Self {
+        self.content = HttpBody::Chunks(Box::new(chunks));
+        self.with_content_type(content_type)
+    }
+
+    //Useful to stream content generated by another thread, the response ends when the sender is dropped
+    pub fn with_channel_content(
+        mut self,
+        receiver: Receiver<Vec<u8>>,
+        content_type: HttpContentType,
+    ) -> Self {
+        self.content = HttpBody::Channel(receiver);
+        self.with_content_type(content_type)
+    }
+
+    //Also sets the Content-Type header, so both always match
+    pub fn with_content_type(mut self, content_type: HttpContentType) -> Self {
+        let header_val = content_type.to_string();
         self.content_type = Some(content_type);
+        self.with_header(String::from(header_map::CONTENT_TYPE), header_val)
+    }
+
+    pub fn with_status(mut self, status_code: StatusCode) -> HttpResponse {
+        self.status_code = status_code;
         self
     }
 
@@ -108,56 +159,297 @@ impl HttpResponse {
         self
     }
 
+    pub fn created(mut self) -> HttpResponse {
+        self.status_code = StatusCode::_201;
+        self
+    }
+
+    pub fn accepted(mut self) -> HttpResponse {
+        self.status_code = StatusCode::_202;
+        self
+    }
+
+    pub fn no_content(mut self) -> HttpResponse {
+        self.status_code = StatusCode::_204;
+        self
+    }
+
+    pub fn moved_permanently(self, location: &str) -> HttpResponse {
+        self.redirect(StatusCode::_301, location)
+    }
+
+    pub fn found(self, location: &str) -> HttpResponse {
+        self.redirect(StatusCode::_302, location)
+    }
+
+    pub fn see_other(self, location: &str) -> HttpResponse {
+        self.redirect(StatusCode::_303, location)
+    }
+
+    pub fn not_modified(mut self) -> HttpResponse {
+        self.status_code = StatusCode::_304;
+        self
+    }
+
+    pub fn temporary_redirect(self, location: &str) -> HttpResponse {
+        self.redirect(StatusCode::_307, location)
+    }
+
+    pub fn permanent_redirect(self, location: &str) -> HttpResponse {
+        self.redirect(StatusCode::_308, location)
+    }
+
+    pub fn redirect(mut self, status_code: StatusCode, location: &str) -> HttpResponse {
+        self.status_code = status_code;
+        self.with_header(String::from("Location"), String::from(location))
+    }
+
+    pub fn bad_request(mut self) -> HttpResponse {
+        self.status_code = StatusCode::_400;
+        self
+    }
+
+    pub fn unauthorized(mut self) -> HttpResponse {
+        self.status_code = StatusCode::_401;
+        self
+    }
+
+    pub fn forbidden(mut self) -> HttpResponse {
+        self.status_code = StatusCode::_403;
+        self
+    }
+
     pub fn not_found(mut self) -> HttpResponse {
         self.status_code = StatusCode::_404;
         self
     }
 
+    pub fn method_not_allowed(mut self) -> HttpResponse {
+        self.status_code = StatusCode::_405;
+        self
+    }
+
+    pub fn conflict(mut self) -> HttpResponse {
+        self.status_code = StatusCode::_409;
+        self
+    }
+
+    pub fn unprocessable_entity(mut self) -> HttpResponse {
+        self.status_code = StatusCode::_422;
+        self
+    }
+
+    pub fn too_many_requests(mut self) -> HttpResponse {
+        self.status_code = StatusCode::_429;
+        self
+    }
+
+    pub fn internal_server_error(mut self) -> HttpResponse {
+        self.status_code = StatusCode::_500;
+        self
+    }
+
+    pub fn not_implemented(mut self) -> HttpResponse {
+        self.status_code = StatusCode::_501;
+        self
+    }
+
+    pub fn service_unavailable(mut self) -> HttpResponse {
+        self.status_code = StatusCode::_503;
+        self
+    }
+
     //Explicitly convert the data so we don't need to re-allocate memory
     pub fn content_as_string(self) -> String {
-        String::from_utf8(self.content.unwrap()).unwrap()
+        String::from_utf8(self.content.into_bytes().unwrap()).unwrap()
     }
 
+    //Replaces any previous value of the header
     pub fn with_header(mut self, header_key: String, header_val: String) -> HttpResponse {
-        if header_key == "Content-Length" {
-            panic!("Unable to set custom Content Length");
-        }
-        self.headers.insert(header_key, header_val);
+        check_custom_header(&header_key);
+        self.headers.insert(&header_key, &header_val);
         self
     }
+
+    //Keeps previous values, needed for headers like Set-Cookie
+    pub fn append_header(mut self, header_key: String, header_val: String) -> HttpResponse {
+        check_custom_header(&header_key);
+        self.headers.append(&header_key, &header_val);
+        self
+    }
+
+    pub fn with_trailer(mut self, trailer_key: String, trailer_val: String) -> HttpResponse {
+        self.trailers.append(&trailer_key, &trailer_val);
+        self
+    }
+}
+
+fn check_custom_header(header_key: &str) {
+    //Message framing is decided when the response is written
+    if header_key.eq_ignore_ascii_case(header_map::CONTENT_LENGTH)
+        || header_key.eq_ignore_ascii_case(header_map::TRANSFER_ENCODING)
+    {
+        panic!("Unable to set custom {}", header_key);
+    }
 }
 
 pub struct HttpRequest {
     pub method: HttpMethod,
-    pub path: String,
+    pub raw_target: String, //request target exactly as sent by the client
+    pub path: String,       //percent-decoded path, without the query string
+    pub query_string: Option<String>,
+    pub query_params: UrlEncodedParams,
     pub http_version: HttpVersion,
-    pub headers: HashMap<String, String>, //TODO ignoring multiple headers for the same string for now
+    pub headers: HeaderMap,
     pub content: Option<Vec<u8>>,
+    pub body_stream: Option<RequestBody>, //multipart/form-data bodies are left unread in here instead of content
+    pub trailers: HeaderMap,              //trailers are only sent with chunked bodies
     pub route_params: HashMap<String, String>, //route_params are added by the router to the request
+    pub matched_route: Option<String>, //pattern of the route the router called, e.g. "/users/{id}"
+    pub state: Arc<Extensions>,        //shared by every request, set with HttpServer::with_state
+    pub extensions: Extensions, //per request values, e.g. the user found by an auth middleware
+    pub connection: Option<ConnectionInfo>, //only None for requests that didn't come from a connection
 }
 
 impl HttpRequest {
-    pub fn new(method: HttpMethod, path: String) -> Self {
+    //Escapes that can't be decoded are kept as they were sent in the path
+    pub fn new(method: HttpMethod, target: String) -> Self {
+        let (raw_path, query_string) = split_target(&target);
+        let path = percent_decode(raw_path).unwrap_or_else(|| String::from(raw_path));
+        let query_string = query_string.map(String::from);
+        let query_params = match &query_string {
+            Some(query_string) => UrlEncodedParams::parse(query_string),
+            None => UrlEncodedParams::new(),
+        };
         HttpRequest {
             method,
+            raw_target: target,
             path,
+            query_string,
+            query_params,
             http_version: HttpVersion::_1_1,
-            headers: HashMap::new(),
+            headers: HeaderMap::new(),
             content: None,
+            body_stream: None,
+            trailers: HeaderMap::new(),
             route_params: HashMap::new(),
+            matched_route: None,
+            state: Arc::new(Extensions::new()),
+            extensions: Extensions::new(),
+            connection: None,
+        }
+    }
+
+    //HTTP/1.1 connections are persistent unless the client asks to close, HTTP/1.0 ones are the opposite
+    pub fn wants_keep_alive(&self) -> bool {
+        let has_option = |option: &str| {
+            self.headers
+                .get_all(header_map::CONNECTION)
+                .flat_map(|x| x.split(','))
+                .any(|x| x.trim().eq_ignore_ascii_case(option))
+        };
+        if has_option("close") {
+            return false;
+        }
+        match self.http_version {
+            HttpVersion::_1_0 => has_option("keep-alive"),
+            HttpVersion::_1_1 => true,
+        }
+    }
+
+    //Route param converted to the wanted type, e.g. request.param::<u64>("id")
+    pub fn param<T: FromStr>(&self, name: &str) -> Result<T, ParamError> {
+        let value = self
+            .route_params
+            .get(name)
+            .ok_or_else(|| ParamError::Missing(String::from(name)))?;
+        value.parse().map_err(|_| ParamError::Invalid {
+            name: String::from(name),
+            value: value.clone(),
+        })
+    }
+
+    //Value registered with HttpServer::with_state
+    pub fn state<T: Send + Sync + 'static>(&self) -> Option<&T> {
+        self.state.get()
+    }
+
+    pub fn peer_addr(&self) -> Option<SocketAddr> {
+        self.connection.map(|x| x.peer_addr)
+    }
+
+    //Path as sent by the client, segments must be decoded one by one since %2F is not a separator
+    pub fn raw_path(&self) -> &str {
+        split_target(&self.raw_target).0
+    }
+
+    pub fn accepts_trailers(&self) -> bool {
+        self.headers
+            .get_all(header_map::TE)
+            .flat_map(|x| x.split(','))
+            .any(|x| x.trim().eq_ignore_ascii_case("trailers"))
+    }
+}
+
+//Splits the path from the query, absolute targets used with proxies are reduced to their path
+pub(crate) fn split_target(target: &str) -> (&str, Option<&str>) {
+    let mut path = target;
+    for scheme in &["http://", "https://"] {
+        if let Some(without_scheme) = target.strip_prefix(scheme) {
+            path = match without_scheme.find(['/', '?']) {
+                Some(start) => &without_scheme[start..],
+                None => "/",
+            };
         }
     }
+    match path.split_once('?') {
+        Some(("", query)) => ("/", Some(query)),
+        Some((path, query)) => (path, Some(query)),
+        None => (path, None),
+    }
 }
 
 impl HttpMethod {
+    //Every method except extension ones, in the order used for the Allow header
+    pub const STANDARD: [HttpMethod; 9] = [
+        HttpMethod::GET,
+        HttpMethod::HEAD,
+        HttpMethod::POST,
+        HttpMethod::PUT,
+        HttpMethod::DELETE,
+        HttpMethod::CONNECT,
+        HttpMethod::OPTIONS,
+        HttpMethod::TRACE,
+        HttpMethod::PATCH,
+    ];
+
     fn from_method_string(value: &str) -> HttpMethod {
         match value {
             "GET" => HttpMethod::GET,
+            "HEAD" => HttpMethod::HEAD,
             "POST" => HttpMethod::POST,
             "PUT" => HttpMethod::PUT,
             "DELETE" => HttpMethod::DELETE,
-            "OPTION" => HttpMethod::OPTION,
-            other => panic!("Unable to find method for '{}'", other),
+            "CONNECT" => HttpMethod::CONNECT,
+            "OPTIONS" => HttpMethod::OPTIONS,
+            "TRACE" => HttpMethod::TRACE,
+            "PATCH" => HttpMethod::PATCH,
+            other => HttpMethod::Extension(String::from(other)),
+        }
+    }
+
+    pub fn to_string(&self) -> &str {
+        match self {
+            HttpMethod::GET => "GET",
+            HttpMethod::HEAD => "HEAD",
+            HttpMethod::POST => "POST",
+            HttpMethod::PUT => "PUT",
+            HttpMethod::DELETE => "DELETE",
+            HttpMethod::CONNECT => "CONNECT",
+            HttpMethod::OPTIONS => "OPTIONS",
+            HttpMethod::TRACE => "TRACE",
+            HttpMethod::PATCH => "PATCH",
+            HttpMethod::Extension(method) => method.as_str(),
         }
     }
 }
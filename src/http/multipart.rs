@@ -0,0 +1,785 @@
+use crate::http::extract::require_content_type;
+use crate::http::header_map::{CONTENT_DISPOSITION, CONTENT_TYPE};
+use crate::http::url_encoding::percent_decode;
+use crate::http::{HeaderMap, HttpContentType, HttpError, HttpRequest};
+use std::collections::hash_map::RandomState;
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::hash::{BuildHasher, Hasher};
+use std::io::{self, Read, Write};
+#[cfg(unix)]
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering::Relaxed;
+
+const READ_SIZE: usize = 8 * 1024;
+const MAX_HEADERS_SIZE: usize = 16 * 1024;
+
+//Read by HttpRequest::multipart from the server state, see HttpServer::set_multipart_config
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MultipartConfig {
+    pub max_parts: usize,
+    pub max_part_size: usize,   //bytes of a single field or file
+    pub max_total_size: usize,  //bytes of the whole body, including boundaries and headers
+    pub spool_threshold: usize, //file parts bigger than this are written to temp_dir instead of kept in memory
+    pub temp_dir: PathBuf,
+}
+
+impl Default for MultipartConfig {
+    fn default() -> Self {
+        MultipartConfig {
+            max_parts: 100,
+            max_part_size: 10 * 1024 * 1024,
+            max_total_size: 50 * 1024 * 1024,
+            spool_threshold: 64 * 1024,
+            temp_dir: env::temp_dir(),
+        }
+    }
+}
+
+//A file written while reading a part, it is deleted on drop unless persisted
+#[derive(Debug)]
+pub struct TempFile {
+    path: PathBuf,
+    size: u64,
+    persisted: bool,
+}
+
+impl TempFile {
+    //The name has a random part and the file is only readable by us, temp_dir is usually shared with other users
+    fn create(dir: &Path) -> io::Result<(TempFile, File)> {
+        static COUNTER: AtomicU64 = AtomicU64::new(0);
+        let mut attempts = 0;
+        loop {
+            //RandomState is seeded from the OS, so the hash of the counter can't be guessed
+            let mut hasher = RandomState::new().build_hasher();
+            hasher.write_u64(COUNTER.fetch_add(1, Relaxed));
+            let name = format!(
+                "web_server-upload-{}-{:016x}",
+                process::id(),
+                hasher.finish()
+            );
+            let path = dir.join(name);
+            let mut options = OpenOptions::new();
+            options.write(true).create_new(true);
+            #[cfg(unix)]
+            options.mode(0o600);
+            match options.open(&path) {
+                Ok(file) => {
+                    let temp_file = TempFile {
+                        path,
+                        size: 0,
+                        persisted: false,
+                    };
+                    return Ok((temp_file, file));
+                }
+                //create_new never opens a file someone else put there
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists && attempts < 10 => {
+                    attempts += 1
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    //Moves the file, copying it when the destination is on another file system
+    pub fn persist<P: AsRef<Path>>(mut self, to: P) -> io::Result<()> {
+        if fs::rename(&self.path, to.as_ref()).is_err() {
+            fs::copy(&self.path, to.as_ref())?;
+            fs::remove_file(&self.path)?;
+        }
+        self.persisted = true;
+        Ok(())
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        if !self.persisted {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum PartContent {
+    Memory(Vec<u8>),
+    File(TempFile),
+}
+
+//A field or file of a multipart/form-data body, with the headers sent for it
+#[derive(Debug)]
+pub struct Part {
+    headers: HeaderMap,
+    name: Option<String>,
+    file_name: Option<String>,
+    content: PartContent,
+}
+
+impl Part {
+    pub fn headers(&self) -> &HeaderMap {
+        &self.headers
+    }
+
+    //Name of the form field
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    //Only the last path component the client sent, but still not safe to use as a path as is
+    pub fn file_name(&self) -> Option<&str> {
+        self.file_name.as_deref()
+    }
+
+    pub fn is_file(&self) -> bool {
+        self.file_name.is_some()
+    }
+
+    pub fn content_type(&self) -> Option<HttpContentType> {
+        self.headers
+            .get(CONTENT_TYPE)
+            .and_then(HttpContentType::parse)
+    }
+
+    pub fn size(&self) -> u64 {
+        match &self.content {
+            PartContent::Memory(bytes) => bytes.len() as u64,
+            PartContent::File(temp_file) => temp_file.size(),
+        }
+    }
+
+    pub fn content(&self) -> &PartContent {
+        &self.content
+    }
+
+    pub fn into_content(self) -> PartContent {
+        self.content
+    }
+
+    pub fn reader(&self) -> io::Result<Box<dyn Read + '_>> {
+        match &self.content {
+            PartContent::Memory(bytes) => Ok(Box::new(bytes.as_slice())),
+            PartContent::File(temp_file) => Ok(Box::new(File::open(temp_file.path())?)),
+        }
+    }
+
+    pub fn bytes(&self) -> io::Result<Vec<u8>> {
+        let mut bytes = Vec::with_capacity(self.size() as usize);
+        self.reader()?.read_to_end(&mut bytes)?;
+        Ok(bytes)
+    }
+
+    pub fn text(&self) -> Result<String, HttpError> {
+        String::from_utf8(self.bytes()?).map_err(|_| {
+            HttpError::bad_request(&format!(
+                "Part {} is not valid UTF-8",
+                self.name().unwrap_or_default()
+            ))
+        })
+    }
+}
+
+enum State {
+    Preamble,
+    Part,
+    Done,
+}
+
+//Reads parts one at a time, only a small buffer of the body is kept in memory besides the current part
+pub struct Multipart<R: Read> {
+    reader: R,
+    config: MultipartConfig,
+    delimiter: Vec<u8>,
+    buffer: Vec<u8>,
+    eof: bool,
+    total_size: usize,
+    parts: usize,
+    state: State,
+}
+
+impl<R: Read> Multipart<R> {
+    pub fn new(reader: R, boundary: &str, config: MultipartConfig) -> Self {
+        Multipart {
+            reader,
+            config,
+            delimiter: format!("\r\n--{}", boundary).into_bytes(),
+            //The first boundary has no line break before it, adding one lets every delimiter be found the same way
+            buffer: b"\r\n".to_vec(),
+            eof: false,
+            total_size: 0,
+            parts: 0,
+            state: State::Preamble,
+        }
+    }
+
+    //Ok(None) after the closing boundary, nothing is read after an error
+    pub fn next_part(&mut self) -> Result<Option<Part>, HttpError> {
+        let result = self.read_part();
+        if result.is_err() {
+            self.state = State::Done;
+        }
+        result
+    }
+
+    fn read_part(&mut self) -> Result<Option<Part>, HttpError> {
+        if let State::Preamble = self.state {
+            let mut preamble = PartSink::discard(self.config.max_total_size);
+            self.read_until_delimiter(&mut preamble)?;
+            self.read_after_delimiter()?;
+        }
+        if let State::Done = self.state {
+            return Ok(None);
+        }
+
+        self.parts += 1;
+        if self.parts > self.config.max_parts {
+            return Err(HttpError::payload_too_large(&format!(
+                "Multipart body has more than {} parts",
+                self.config.max_parts
+            )));
+        }
+        let headers = self.read_headers()?;
+        let (name, file_name) = match headers.get(CONTENT_DISPOSITION) {
+            Some(disposition) => parse_disposition(disposition),
+            None => (None, None),
+        };
+        let mut sink = PartSink::new(&self.config, file_name.is_some());
+        self.read_until_delimiter(&mut sink)?;
+        let content = sink.finish()?;
+        self.read_after_delimiter()?;
+        Ok(Some(Part {
+            headers,
+            name,
+            file_name,
+            content,
+        }))
+    }
+
+    //Returns false once the body ended
+    fn fill(&mut self) -> Result<bool, HttpError> {
+        if self.eof {
+            return Ok(false);
+        }
+        let start = self.buffer.len();
+        self.buffer.resize(start + READ_SIZE, 0);
+        let read = match self.reader.read(&mut self.buffer[start..]) {
+            Ok(read) => read,
+            Err(e) => {
+                self.buffer.truncate(start);
+                return Err(e.into());
+            }
+        };
+        self.buffer.truncate(start + read);
+        if read == 0 {
+            self.eof = true;
+            return Ok(false);
+        }
+        self.total_size += read;
+        if self.total_size > self.config.max_total_size {
+            return Err(HttpError::payload_too_large(&format!(
+                "Multipart body is bigger than {} bytes",
+                self.config.max_total_size
+            )));
+        }
+        Ok(true)
+    }
+
+    //Everything before the next delimiter goes to the sink, the delimiter itself is consumed
+    fn read_until_delimiter(&mut self, sink: &mut PartSink) -> Result<(), HttpError> {
+        loop {
+            if let Some(start) = find(&self.buffer, &self.delimiter) {
+                sink.write(&self.buffer[..start])?;
+                self.buffer.drain(..start + self.delimiter.len());
+                return Ok(());
+            }
+            //The end of the buffer might be the start of a delimiter, so it is kept for the next search
+            let keep = self.delimiter.len() - 1;
+            if self.buffer.len() > keep {
+                let end = self.buffer.len() - keep;
+                sink.write(&self.buffer[..end])?;
+                self.buffer.drain(..end);
+            }
+            if !self.fill()? {
+                return Err(HttpError::bad_request(
+                    "Multipart body ended before the closing boundary",
+                ));
+            }
+        }
+    }
+
+    //A delimiter is followed by "--" on the last one, or by optional whitespace and a line break
+    fn read_after_delimiter(&mut self) -> Result<(), HttpError> {
+        loop {
+            if self.buffer.starts_with(b"--") {
+                //The epilogue after the closing boundary is ignored
+                self.state = State::Done;
+                return Ok(());
+            }
+            let padding = self
+                .buffer
+                .iter()
+                .take_while(|x| **x == b' ' || **x == b'\t')
+                .count();
+            let rest = &self.buffer[padding..];
+            if rest.starts_with(b"\r\n") {
+                self.buffer.drain(..padding + 2);
+                self.state = State::Part;
+                return Ok(());
+            }
+            //Not enough was read yet to tell what follows the delimiter
+            let incomplete = rest.is_empty() || rest == b"\r" || self.buffer == b"-";
+            if !incomplete || padding > MAX_HEADERS_SIZE || !self.fill()? {
+                return Err(HttpError::bad_request("Malformed multipart boundary"));
+            }
+        }
+    }
+
+    fn read_headers(&mut self) -> Result<HeaderMap, HttpError> {
+        let end = loop {
+            if self.buffer.starts_with(b"\r\n") {
+                self.buffer.drain(..2);
+                return Ok(HeaderMap::new());
+            }
+            if let Some(end) = find(&self.buffer, b"\r\n\r\n") {
+                break end;
+            }
+            if self.buffer.len() > MAX_HEADERS_SIZE {
+                return Err(HttpError::payload_too_large(
+                    "Multipart part headers are too large",
+                ));
+            }
+            if !self.fill()? {
+                return Err(HttpError::bad_request(
+                    "Multipart part headers not finished",
+                ));
+            }
+        };
+        let section: Vec<u8> = self.buffer.drain(..end + 4).collect();
+        let section = std::str::from_utf8(&section[..end])
+            .map_err(|_| HttpError::bad_request("Multipart part headers are not valid UTF-8"))?;
+        let mut headers = HeaderMap::new();
+        for line in section.split("\r\n") {
+            match line.split_once(':') {
+                Some((name, value)) if !name.trim().is_empty() => {
+                    headers.append(name.trim(), value.trim())
+                }
+                _ => return Err(HttpError::bad_request("Malformed multipart part header")),
+            }
+        }
+        Ok(headers)
+    }
+}
+
+impl<R: Read> Iterator for Multipart<R> {
+    type Item = Result<Part, HttpError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_part().transpose()
+    }
+}
+
+enum SinkContent {
+    Discard,
+    Memory(Vec<u8>),
+    File(TempFile, File),
+}
+
+//Where the content of a part goes while it is read, file parts move to a temp file past the spool threshold
+struct PartSink {
+    content: SinkContent,
+    size: usize,
+    limit: usize,
+    spool: Option<(usize, PathBuf)>, //threshold and directory, only set for files
+}
+
+impl PartSink {
+    fn new(config: &MultipartConfig, is_file: bool) -> Self {
+        let spool = if is_file {
+            Some((config.spool_threshold, config.temp_dir.clone()))
+        } else {
+            None
+        };
+        PartSink {
+            content: SinkContent::Memory(Vec::new()),
+            size: 0,
+            limit: config.max_part_size,
+            spool,
+        }
+    }
+
+    fn discard(limit: usize) -> Self {
+        PartSink {
+            content: SinkContent::Discard,
+            size: 0,
+            limit,
+            spool: None,
+        }
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<(), HttpError> {
+        self.size += data.len();
+        if self.size > self.limit {
+            return Err(HttpError::payload_too_large(&format!(
+                "Multipart part is bigger than {} bytes",
+                self.limit
+            )));
+        }
+        if let (SinkContent::Memory(bytes), Some((threshold, dir))) = (&self.content, &self.spool) {
+            if self.size > *threshold {
+                let (temp_file, mut file) = TempFile::create(dir)?;
+                file.write_all(bytes)?;
+                self.content = SinkContent::File(temp_file, file);
+            }
+        }
+        match &mut self.content {
+            SinkContent::Discard => {}
+            SinkContent::Memory(bytes) => bytes.extend_from_slice(data),
+            SinkContent::File(_, file) => file.write_all(data)?,
+        }
+        Ok(())
+    }
+
+    fn finish(self) -> Result<PartContent, HttpError> {
+        match self.content {
+            SinkContent::Discard => Ok(PartContent::Memory(Vec::new())),
+            SinkContent::Memory(bytes) => Ok(PartContent::Memory(bytes)),
+            SinkContent::File(mut temp_file, mut file) => {
+                file.flush()?;
+                temp_file.size = self.size as u64;
+                Ok(PartContent::File(temp_file))
+            }
+        }
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+//Name and file name from 'form-data; name="field"; filename="a.txt"', filename* (RFC 5987) wins over filename
+fn parse_disposition(value: &str) -> (Option<String>, Option<String>) {
+    let mut name = None;
+    let mut file_name = None;
+    let mut extended_file_name = None;
+    for parameter in split_parameters(value).into_iter().skip(1) {
+        let (key, val) = match parameter.split_once('=') {
+            Some((key, val)) => (key.trim().to_ascii_lowercase(), val.trim()),
+            None => continue,
+        };
+        match key.as_str() {
+            "name" => name = Some(unquote(val)),
+            "filename" => file_name = Some(unquote(val)),
+            "filename*" => {
+                extended_file_name = val
+                    .split_once("''")
+                    .filter(|(charset, _)| charset.eq_ignore_ascii_case("utf-8"))
+                    .and_then(|(_, encoded)| percent_decode(encoded))
+            }
+            _ => {}
+        }
+    }
+    let file_name = extended_file_name.or(file_name).map(|x| {
+        //Some browsers send the full path of the file on the client
+        let start = x.rfind(['/', '\\']).map_or(0, |i| i + 1);
+        String::from(&x[start..])
+    });
+    (name, file_name)
+}
+
+//Splits on ';' outside of quoted strings
+fn split_parameters(value: &str) -> Vec<&str> {
+    let mut parameters = Vec::new();
+    let mut start = 0;
+    let mut quoted = false;
+    let mut escaped = false;
+    for (i, c) in value.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            ';' if !quoted => {
+                parameters.push(value[start..i].trim());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    parameters.push(value[start..].trim());
+    parameters
+}
+
+fn unquote(value: &str) -> String {
+    match value.strip_prefix('"').and_then(|x| x.strip_suffix('"')) {
+        Some(inner) => {
+            let mut result = String::with_capacity(inner.len());
+            let mut chars = inner.chars();
+            while let Some(c) = chars.next() {
+                match c {
+                    '\\' => result.extend(chars.next()),
+                    c => result.push(c),
+                }
+            }
+            result
+        }
+        None => String::from(value),
+    }
+}
+
+impl HttpRequest {
+    //415 unless the body is multipart/form-data and 400 without a boundary, the limits apply while reading the parts
+    //The server leaves multipart bodies on the connection, so parts are read from it as they arrive
+    pub fn multipart(&self) -> Result<Multipart<Box<dyn Read + '_>>, HttpError> {
+        let config = self.state::<MultipartConfig>().cloned().unwrap_or_default();
+        self.multipart_with_config(config)
+    }
+
+    pub fn multipart_with_config(
+        &self,
+        config: MultipartConfig,
+    ) -> Result<Multipart<Box<dyn Read + '_>>, HttpError> {
+        require_content_type(self, &HttpContentType::MULTIPART_FORM_DATA)?;
+        let content_type = self
+            .headers
+            .get(CONTENT_TYPE)
+            .and_then(HttpContentType::parse);
+        let boundary = content_type
+            .as_ref()
+            .and_then(|x| x.parameter("boundary"))
+            .filter(|x| !x.is_empty() && x.len() <= 70)
+            .ok_or_else(|| HttpError::bad_request("Missing multipart boundary"))?;
+        let reader: Box<dyn Read> = match &self.body_stream {
+            Some(body_stream) => {
+                if body_stream.length().unwrap_or_default() > config.max_total_size {
+                    return Err(HttpError::payload_too_large(&format!(
+                        "Multipart body is bigger than {} bytes",
+                        config.max_total_size
+                    )));
+                }
+                Box::new(body_stream.clone())
+            }
+            None => Box::new(self.content.as_deref().unwrap_or_default()),
+        };
+        Ok(Multipart::new(reader, boundary, config))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::http::http_parser::BodyFraming;
+    use crate::http::multipart::{parse_disposition, Multipart, MultipartConfig, PartContent};
+    use crate::http::request_body::RequestBody;
+    use crate::http::{HttpError, HttpMethod, HttpRequest, StatusCode};
+    use std::env;
+    use std::fs;
+    use std::io::{self, Read};
+
+    const BODY: &[u8] = b"preamble\r\n--XyZ\r\n\
+        Content-Disposition: form-data; name=\"title\"\r\n\r\n\
+        Hello\r\nworld\r\n--XyZ  \r\n\
+        Content-Disposition: form-data; name=\"upload\"; filename=\"C:\\\\docs\\\\a.txt\"\r\n\
+        Content-Type: text/plain\r\n\r\n\
+        --XyZ is not a boundary here\r\n--XyZ--\r\nepilogue";
+
+    //Hands out one byte per read, so every delimiter is split between reads
+    struct SlowReader<'a>(&'a [u8]);
+
+    impl Read for SlowReader<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.0.is_empty() || buf.is_empty() {
+                return Ok(0);
+            }
+            buf[0] = self.0[0];
+            self.0 = &self.0[1..];
+            Ok(1)
+        }
+    }
+
+    fn error_status<T>(result: Result<T, HttpError>) -> StatusCode {
+        result.err().unwrap().status_code()
+    }
+
+    #[test]
+    fn it_reads_fields_and_files() {
+        let multipart = Multipart::new(SlowReader(BODY), "XyZ", MultipartConfig::default());
+        let parts: Vec<_> = multipart.collect::<Result<_, _>>().unwrap();
+        assert_eq!(parts.len(), 2);
+        assert_eq!(parts[0].name(), Some("title"));
+        assert!(!parts[0].is_file());
+        assert_eq!(parts[0].text().unwrap(), "Hello\r\nworld");
+        assert_eq!(parts[1].name(), Some("upload"));
+        assert_eq!(parts[1].file_name(), Some("a.txt"));
+        assert_eq!(parts[1].content_type().unwrap().essence(), "text/plain");
+        assert_eq!(parts[1].text().unwrap(), "--XyZ is not a boundary here");
+    }
+
+    #[test]
+    fn it_spools_big_files() {
+        let config = MultipartConfig {
+            spool_threshold: 10,
+            ..MultipartConfig::default()
+        };
+        let mut multipart = Multipart::new(BODY, "XyZ", config);
+        let field = multipart.next_part().unwrap().unwrap();
+        assert!(matches!(field.content(), PartContent::Memory(_)));
+        let file = multipart.next_part().unwrap().unwrap();
+        assert!(multipart.next_part().unwrap().is_none());
+
+        let path = match file.content() {
+            PartContent::File(temp_file) => temp_file.path().to_path_buf(),
+            PartContent::Memory(_) => panic!("File part kept in memory"),
+        };
+        assert_eq!(file.size(), 28);
+        assert_eq!(fs::read(&path).unwrap(), b"--XyZ is not a boundary here");
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+        drop(file);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn it_persists_spooled_files() {
+        let config = MultipartConfig {
+            spool_threshold: 0,
+            ..MultipartConfig::default()
+        };
+        let file = Multipart::new(BODY, "XyZ", config).nth(1).unwrap().unwrap();
+        let destination = env::temp_dir().join(format!("persisted-{}.txt", std::process::id()));
+        match file.into_content() {
+            PartContent::File(temp_file) => temp_file.persist(&destination).unwrap(),
+            PartContent::Memory(_) => panic!("File part kept in memory"),
+        }
+        assert_eq!(
+            fs::read(&destination).unwrap(),
+            b"--XyZ is not a boundary here"
+        );
+        fs::remove_file(destination).unwrap();
+    }
+
+    #[test]
+    fn it_enforces_limits() {
+        let with_config = |config| Multipart::new(BODY, "XyZ", config);
+        let mut multipart = with_config(MultipartConfig {
+            max_part_size: 12,
+            ..MultipartConfig::default()
+        });
+        assert!(multipart.next_part().is_ok());
+        assert_eq!(error_status(multipart.next_part()), StatusCode::_413);
+        assert!(multipart.next_part().unwrap().is_none());
+
+        let mut multipart = with_config(MultipartConfig {
+            max_total_size: 64,
+            ..MultipartConfig::default()
+        });
+        assert_eq!(error_status(multipart.next_part()), StatusCode::_413);
+
+        let mut multipart = with_config(MultipartConfig {
+            max_parts: 1,
+            ..MultipartConfig::default()
+        });
+        assert!(multipart.next_part().is_ok());
+        assert_eq!(error_status(multipart.next_part()), StatusCode::_413);
+    }
+
+    #[test]
+    fn it_rejects_malformed_bodies() {
+        let body = &BODY[..BODY.len() - 20];
+        let mut multipart = Multipart::new(body, "XyZ", MultipartConfig::default());
+        assert!(multipart.next_part().is_ok());
+        assert_eq!(error_status(multipart.next_part()), StatusCode::_400);
+
+        let mut multipart = Multipart::new(
+            &b"--XyZ\r\nbroken\r\n\r\n"[..],
+            "XyZ",
+            MultipartConfig::default(),
+        );
+        assert_eq!(error_status(multipart.next_part()), StatusCode::_400);
+    }
+
+    #[test]
+    fn it_parses_content_disposition() {
+        assert_eq!(
+            parse_disposition("form-data; name=\"a;b\"; filename=\"say \\\"hi\\\".txt\""),
+            (
+                Some(String::from("a;b")),
+                Some(String::from("say \"hi\".txt"))
+            )
+        );
+        assert_eq!(
+            parse_disposition(
+                "form-data; name=doc; filename=\"x\"; filename*=UTF-8''%C3%A9t%C3%A9.pdf"
+            ),
+            (Some(String::from("doc")), Some(String::from("été.pdf")))
+        );
+        assert_eq!(
+            parse_disposition("form-data; filename=\"/etc/passwd\""),
+            (None, Some(String::from("passwd")))
+        );
+    }
+
+    #[test]
+    fn it_checks_request_content_type() {
+        let mut request = HttpRequest::new(HttpMethod::POST, String::from("/upload"));
+        request.content = Some(BODY.to_vec());
+        assert_eq!(error_status(request.multipart()), StatusCode::_415);
+
+        request
+            .headers
+            .insert("Content-Type", "multipart/form-data");
+        assert_eq!(error_status(request.multipart()), StatusCode::_400);
+
+        request
+            .headers
+            .insert("Content-Type", "multipart/form-data; boundary=\"XyZ\"");
+        assert_eq!(request.multipart().unwrap().count(), 2);
+    }
+
+    #[test]
+    fn it_reads_streamed_bodies() {
+        let streamed_request = || {
+            let mut request = HttpRequest::new(HttpMethod::POST, String::from("/upload"));
+            request
+                .headers
+                .insert("Content-Type", "multipart/form-data; boundary=XyZ");
+            let connection = Box::new(io::Cursor::new(BODY.to_vec()));
+            request.body_stream = Some(RequestBody::new(
+                connection,
+                BodyFraming::Length(BODY.len()),
+            ));
+            request
+        };
+        let request = streamed_request();
+        let parts: Vec<_> = request
+            .multipart()
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(parts[1].text().unwrap(), "--XyZ is not a boundary here");
+
+        //Bodies announced as too big are refused before anything is read
+        let config = MultipartConfig {
+            max_total_size: BODY.len() - 1,
+            ..MultipartConfig::default()
+        };
+        let request = streamed_request();
+        assert_eq!(
+            error_status(request.multipart_with_config(config)),
+            StatusCode::_413
+        );
+    }
+}
@@ -0,0 +1,128 @@
+use crate::http::http_parser::{BodyFraming, ChunkedReader};
+use std::io::{self, BufRead, Read, Take};
+use std::sync::{Arc, Mutex, PoisonError};
+
+//The connection as seen by a request body, the server gets it back once the request is handled
+pub(crate) type ConnectionReader = Box<dyn BufRead + Send>;
+
+enum BodyReader {
+    Length(Take<ConnectionReader>),
+    Chunked(ChunkedReader<ConnectionReader>),
+}
+
+impl Read for BodyReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            BodyReader::Length(reader) => reader.read(buf),
+            BodyReader::Chunked(reader) => reader.read(buf),
+        }
+    }
+}
+
+//Body the server left on the connection for the handler to read, only multipart/form-data bodies are streamed
+//Clones read from the same connection, nothing can be read anymore once the handler returned
+#[derive(Clone)]
+pub struct RequestBody {
+    reader: Arc<Mutex<Option<BodyReader>>>,
+    length: Option<usize>, //None for chunked bodies
+}
+
+impl RequestBody {
+    pub(crate) fn new(connection: ConnectionReader, framing: BodyFraming) -> Self {
+        let (reader, length) = match framing {
+            BodyFraming::None => (BodyReader::Length(connection.take(0)), Some(0)),
+            BodyFraming::Length(size) => {
+                (BodyReader::Length(connection.take(size as u64)), Some(size))
+            }
+            BodyFraming::Chunked => (BodyReader::Chunked(ChunkedReader::new(connection)), None),
+        };
+        RequestBody {
+            reader: Arc::new(Mutex::new(Some(reader))),
+            length,
+        }
+    }
+
+    //Content-Length sent by the client
+    pub fn length(&self) -> Option<usize> {
+        self.length
+    }
+
+    //Skips what the handler didn't read and gives the connection back for the next request
+    //None when more than max_skipped bytes were left or the body was broken, the connection must be closed then
+    pub(crate) fn finish(self, max_skipped: usize) -> Option<ConnectionReader> {
+        let mut reader = self
+            .reader
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take()?;
+        let limit = (max_skipped as u64).saturating_add(1);
+        let skipped = io::copy(&mut (&mut reader).take(limit), &mut io::sink()).ok()?;
+        if skipped > max_skipped as u64 {
+            return None;
+        }
+        match reader {
+            BodyReader::Length(reader) if reader.limit() == 0 => Some(reader.into_inner()),
+            //The client closed the connection before sending the whole body
+            BodyReader::Length(_) => None,
+            BodyReader::Chunked(reader) => Some(reader.into_inner()),
+        }
+    }
+}
+
+impl Read for RequestBody {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut reader = self.reader.lock().unwrap_or_else(PoisonError::into_inner);
+        match reader.as_mut() {
+            Some(reader) => reader.read(buf),
+            None => Ok(0),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::http::http_parser::BodyFraming;
+    use crate::http::request_body::RequestBody;
+    use std::io::{BufRead, Cursor, Read};
+
+    fn body(content: &str, framing: BodyFraming) -> RequestBody {
+        RequestBody::new(Box::new(Cursor::new(content.as_bytes().to_vec())), framing)
+    }
+
+    fn rest(mut connection: Box<dyn BufRead + Send>) -> String {
+        let mut rest = String::new();
+        connection.read_to_string(&mut rest).unwrap();
+        rest
+    }
+
+    #[test]
+    fn it_reads_only_the_body() {
+        let mut request_body = body("hello GET /next", BodyFraming::Length(5));
+        let mut content = String::new();
+        request_body.clone().read_to_string(&mut content).unwrap();
+        assert_eq!(content, "hello");
+        assert_eq!(request_body.read(&mut [0; 4]).unwrap(), 0);
+        assert_eq!(rest(request_body.finish(0).unwrap()), " GET /next");
+
+        let request_body = body("3\r\nabc\r\n0\r\n\r\nGET /next", BodyFraming::Chunked);
+        let mut content = String::new();
+        request_body.clone().read_to_string(&mut content).unwrap();
+        assert_eq!(content, "abc");
+        assert_eq!(rest(request_body.finish(0).unwrap()), "GET /next");
+    }
+
+    #[test]
+    fn it_skips_unread_bodies_up_to_the_limit() {
+        let request_body = body("hello GET /next", BodyFraming::Length(5));
+        assert_eq!(rest(request_body.finish(5).unwrap()), " GET /next");
+
+        let request_body = body("hello GET /next", BodyFraming::Length(5));
+        assert!(request_body.finish(4).is_none());
+
+        let request_body = body("hel", BodyFraming::Length(5));
+        assert!(request_body.finish(5).is_none());
+
+        let request_body = body("3\r\nabc\r\nzz\r\n", BodyFraming::Chunked);
+        assert!(request_body.finish(10).is_none());
+    }
+}
@@ -0,0 +1,126 @@
+use regex::Regex;
+use std::error::Error;
+use std::fmt;
+
+//Checked by the router while matching, a segment that fails it makes the router try the next route
+pub(crate) enum ParamConstraint {
+    Any,
+    Integer { min: i128, max: i128 },
+    Uuid,
+    Pattern(Regex),
+}
+
+impl ParamConstraint {
+    //Built-in names are u8..u64, i8..i64 and uuid, anything else is a regular expression for the whole segment
+    pub(crate) fn parse(constraint: &str) -> Result<ParamConstraint, String> {
+        let integer = |min, max| ParamConstraint::Integer { min, max };
+        match constraint {
+            "" => Ok(ParamConstraint::Any),
+            "u8" => Ok(integer(0, u8::MAX.into())),
+            "u16" => Ok(integer(0, u16::MAX.into())),
+            "u32" => Ok(integer(0, u32::MAX.into())),
+            "u64" => Ok(integer(0, u64::MAX.into())),
+            "i8" => Ok(integer(i8::MIN.into(), i8::MAX.into())),
+            "i16" => Ok(integer(i16::MIN.into(), i16::MAX.into())),
+            "i32" => Ok(integer(i32::MIN.into(), i32::MAX.into())),
+            "i64" => Ok(integer(i64::MIN.into(), i64::MAX.into())),
+            "uuid" => Ok(ParamConstraint::Uuid),
+            pattern => Regex::new(&format!("^(?:{})$", pattern))
+                .map(ParamConstraint::Pattern)
+                .map_err(|e| e.to_string()),
+        }
+    }
+
+    pub(crate) fn matches(&self, value: &str) -> bool {
+        match self {
+            ParamConstraint::Any => true,
+            //A leading '+' is accepted by parse but is not a canonical integer in a path
+            ParamConstraint::Integer { min, max } => {
+                !value.starts_with('+')
+                    && value.parse::<i128>().is_ok_and(|x| *min <= x && x <= *max)
+            }
+            ParamConstraint::Uuid => is_uuid(value),
+            ParamConstraint::Pattern(regex) => regex.is_match(value),
+        }
+    }
+
+    pub(crate) fn is_any(&self) -> bool {
+        matches!(self, ParamConstraint::Any)
+    }
+}
+
+fn is_uuid(value: &str) -> bool {
+    let groups: Vec<&str> = value.split('-').collect();
+    let lengths = [8, 4, 4, 4, 12];
+    groups.len() == lengths.len()
+        && groups.iter().zip(lengths.iter()).all(|(group, length)| {
+            group.len() == *length && group.bytes().all(|x| x.is_ascii_hexdigit())
+        })
+}
+
+//Splits "{name:constraint}" or the older "?name" into the name and the constraint
+pub(crate) fn parse_param_segment(segment: &str) -> Option<(&str, &str)> {
+    if let Some(name) = segment.strip_prefix('?') {
+        return Some((name, ""));
+    }
+    let inner = segment.strip_prefix('{')?.strip_suffix('}')?;
+    match inner.split_once(':') {
+        Some((name, constraint)) => Some((name, constraint)),
+        None => Some((inner, "")),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParamError {
+    Missing(String),
+    Invalid { name: String, value: String },
+}
+
+impl fmt::Display for ParamError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParamError::Missing(name) => write!(f, "Missing route parameter {}", name),
+            ParamError::Invalid { name, value } => {
+                write!(f, "Invalid value for route parameter {}: {}", name, value)
+            }
+        }
+    }
+}
+
+impl Error for ParamError {}
+
+#[cfg(test)]
+mod tests {
+    use crate::http::route_param::{parse_param_segment, ParamConstraint};
+
+    #[test]
+    fn it_parses_param_segments() {
+        assert_eq!(parse_param_segment("?id"), Some(("id", "")));
+        assert_eq!(parse_param_segment("{id}"), Some(("id", "")));
+        assert_eq!(parse_param_segment("{id:u64}"), Some(("id", "u64")));
+        assert_eq!(
+            parse_param_segment("{slug:[a-z-]{2,}}"),
+            Some(("slug", "[a-z-]{2,}"))
+        );
+        assert_eq!(parse_param_segment("users"), None);
+    }
+
+    #[test]
+    fn it_checks_constraints() {
+        let u8_constraint = ParamConstraint::parse("u8").unwrap();
+        assert!(u8_constraint.matches("255"));
+        assert!(!u8_constraint.matches("-1"));
+        assert!(!u8_constraint.matches("256"));
+        assert!(!u8_constraint.matches("+1"));
+        let i64_constraint = ParamConstraint::parse("i64").unwrap();
+        assert!(i64_constraint.matches("-42"));
+        assert!(!i64_constraint.matches("-"));
+        let uuid = ParamConstraint::parse("uuid").unwrap();
+        assert!(uuid.matches("67e55044-10b1-426f-9247-bb680e5fe0c8"));
+        assert!(!uuid.matches("67e55044-10b1-426f-9247"));
+        let slug = ParamConstraint::parse("[a-z-]+").unwrap();
+        assert!(slug.matches("hello-world"));
+        assert!(!slug.matches("Hello"));
+        assert!(ParamConstraint::parse("[a-z").is_err());
+    }
+}
@@ -0,0 +1,162 @@
+//Generates the StatusCode enum together with its code and reason phrase lookups from a single table
+macro_rules! status_codes {
+    ($(($variant:ident, $code:expr, $reason:expr),)+) => {
+        #[derive(PartialEq, Eq, Debug, Clone, Copy)]
+        pub enum StatusCode {
+            $($variant,)+
+            Custom(u16), //any code outside the IANA registry, sent without reason phrase
+        }
+
+        impl StatusCode {
+            pub fn to_string(&self) -> &str {
+                match self {
+                    $(StatusCode::$variant => $reason,)+
+                    StatusCode::Custom(_) => "",
+                }
+            }
+
+            pub fn to_code(&self) -> u16 {
+                match self {
+                    $(StatusCode::$variant => $code,)+
+                    StatusCode::Custom(code) => *code,
+                }
+            }
+
+            //Registered codes always map to their variant so equality works on the code
+            //None outside of 100..=599, the only classes HTTP defines
+            pub fn from_code(code: u16) -> Option<StatusCode> {
+                match code {
+                    $($code => Some(StatusCode::$variant),)+
+                    100..=599 => Some(StatusCode::Custom(code)),
+                    _ => None,
+                }
+            }
+        }
+    };
+}
+
+status_codes! {
+    (_100, 100, "Continue"),
+    (_101, 101, "Switching Protocols"),
+    (_102, 102, "Processing"),
+    (_103, 103, "Early Hints"),
+    (_200, 200, "OK"),
+    (_201, 201, "Created"),
+    (_202, 202, "Accepted"),
+    (_203, 203, "Non-Authoritative Information"),
+    (_204, 204, "No Content"),
+    (_205, 205, "Reset Content"),
+    (_206, 206, "Partial Content"),
+    (_207, 207, "Multi-Status"),
+    (_208, 208, "Already Reported"),
+    (_226, 226, "IM Used"),
+    (_300, 300, "Multiple Choices"),
+    (_301, 301, "Moved Permanently"),
+    (_302, 302, "Found"),
+    (_303, 303, "See Other"),
+    (_304, 304, "Not Modified"),
+    (_305, 305, "Use Proxy"),
+    (_307, 307, "Temporary Redirect"),
+    (_308, 308, "Permanent Redirect"),
+    (_400, 400, "Bad Request"),
+    (_401, 401, "Unauthorized"),
+    (_402, 402, "Payment Required"),
+    (_403, 403, "Forbidden"),
+    (_404, 404, "Not Found"),
+    (_405, 405, "Method Not Allowed"),
+    (_406, 406, "Not Acceptable"),
+    (_407, 407, "Proxy Authentication Required"),
+    (_408, 408, "Request Timeout"),
+    (_409, 409, "Conflict"),
+    (_410, 410, "Gone"),
+    (_411, 411, "Length Required"),
+    (_412, 412, "Precondition Failed"),
+    (_413, 413, "Content Too Large"),
+    (_414, 414, "URI Too Long"),
+    (_415, 415, "Unsupported Media Type"),
+    (_416, 416, "Range Not Satisfiable"),
+    (_417, 417, "Expectation Failed"),
+    (_421, 421, "Misdirected Request"),
+    (_422, 422, "Unprocessable Content"),
+    (_423, 423, "Locked"),
+    (_424, 424, "Failed Dependency"),
+    (_425, 425, "Too Early"),
+    (_426, 426, "Upgrade Required"),
+    (_428, 428, "Precondition Required"),
+    (_429, 429, "Too Many Requests"),
+    (_431, 431, "Request Header Fields Too Large"),
+    (_451, 451, "Unavailable For Legal Reasons"),
+    (_500, 500, "Internal Server Error"),
+    (_501, 501, "Not Implemented"),
+    (_502, 502, "Bad Gateway"),
+    (_503, 503, "Service Unavailable"),
+    (_504, 504, "Gateway Timeout"),
+    (_505, 505, "HTTP Version Not Supported"),
+    (_506, 506, "Variant Also Negotiates"),
+    (_507, 507, "Insufficient Storage"),
+    (_508, 508, "Loop Detected"),
+    (_510, 510, "Not Extended"),
+    (_511, 511, "Network Authentication Required"),
+}
+
+impl StatusCode {
+    pub fn is_informational(&self) -> bool {
+        (100..200).contains(&self.to_code())
+    }
+
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.to_code())
+    }
+
+    pub fn is_redirect(&self) -> bool {
+        (300..400).contains(&self.to_code())
+    }
+
+    pub fn is_client_error(&self) -> bool {
+        (400..500).contains(&self.to_code())
+    }
+
+    pub fn is_server_error(&self) -> bool {
+        (500..600).contains(&self.to_code())
+    }
+
+    //1xx, 204 and 304 responses never have a body, even if the handler sets one
+    pub fn allows_body(&self) -> bool {
+        !self.is_informational() && *self != StatusCode::_204 && *self != StatusCode::_304
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::http::StatusCode;
+
+    #[test]
+    fn it_maps_codes_to_variants() {
+        assert_eq!(StatusCode::from_code(201), Some(StatusCode::_201));
+        assert_eq!(
+            StatusCode::from_code(429).unwrap().to_string(),
+            "Too Many Requests"
+        );
+        assert_eq!(StatusCode::from_code(299), Some(StatusCode::Custom(299)));
+        assert_eq!(StatusCode::Custom(299).to_code(), 299);
+        assert_eq!(StatusCode::from_code(99), None);
+        assert_eq!(StatusCode::from_code(600), None);
+    }
+
+    #[test]
+    fn it_classifies_codes() {
+        assert!(StatusCode::_204.is_success());
+        assert!(StatusCode::_302.is_redirect());
+        assert!(StatusCode::_422.is_client_error());
+        assert!(StatusCode::Custom(599).is_server_error());
+        assert!(!StatusCode::_500.is_client_error());
+    }
+
+    #[test]
+    fn it_suppresses_body_for_bodyless_codes() {
+        assert!(!StatusCode::_101.allows_body());
+        assert!(!StatusCode::_204.allows_body());
+        assert!(!StatusCode::_304.allows_body());
+        assert!(StatusCode::_200.allows_body());
+    }
+}
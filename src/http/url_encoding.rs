@@ -0,0 +1,189 @@
+//Decodes %XX escapes, returns None for broken escapes or when the result is not valid UTF-8
+pub fn percent_decode(input: &str) -> Option<String> {
+    decode(input, false)
+}
+
+//Same as percent_decode, but '+' is a space as in application/x-www-form-urlencoded
+pub fn form_decode(input: &str) -> Option<String> {
+    decode(input, true)
+}
+
+//Form decoding without the UTF-8 check, for bodies sent in other charsets
+pub fn form_decode_bytes(input: &[u8]) -> Option<Vec<u8>> {
+    decode_bytes(input, true)
+}
+
+fn decode(input: &str, plus_as_space: bool) -> Option<String> {
+    String::from_utf8(decode_bytes(input.as_bytes(), plus_as_space)?).ok()
+}
+
+fn decode_bytes(bytes: &[u8], plus_as_space: bool) -> Option<Vec<u8>> {
+    let mut result = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' => {
+                let hex = bytes.get(i + 1..i + 3)?;
+                if !hex.iter().all(|x| x.is_ascii_hexdigit()) {
+                    return None;
+                }
+                result.push(u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok()?);
+                i += 3;
+            }
+            b'+' if plus_as_space => {
+                result.push(b' ');
+                i += 1;
+            }
+            other => {
+                result.push(other);
+                i += 1;
+            }
+        }
+    }
+    Some(result)
+}
+
+//Escapes everything except unreserved characters, so the result is safe in any part of a URL
+pub fn percent_encode(input: &str) -> String {
+    let mut result = String::with_capacity(input.len());
+    for byte in input.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~".contains(&byte) {
+            result.push(byte as char);
+        } else {
+            result.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    result
+}
+
+//Ordered multimap of the pairs in a query string or urlencoded form
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct UrlEncodedParams {
+    entries: Vec<(String, String)>,
+}
+
+impl UrlEncodedParams {
+    pub fn new() -> Self {
+        UrlEncodedParams::default()
+    }
+
+    //Pairs that can't be decoded are kept as they were sent
+    pub fn parse(input: &str) -> Self {
+        let decode_or_raw = |x: &str| form_decode(x).unwrap_or_else(|| String::from(x));
+        let entries = input
+            .split('&')
+            .filter(|x| !x.is_empty())
+            .map(|pair| match pair.split_once('=') {
+                Some((key, val)) => (decode_or_raw(key), decode_or_raw(val)),
+                None => (decode_or_raw(pair), String::new()),
+            })
+            .collect();
+        UrlEncodedParams { entries }
+    }
+
+    //First value for the key
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.entries
+            .iter()
+            .find(|x| x.0 == key)
+            .map(|x| x.1.as_str())
+    }
+
+    pub fn get_all<'a>(&'a self, key: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.entries
+            .iter()
+            .filter(move |x| x.0 == key)
+            .map(|x| x.1.as_str())
+    }
+
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.get(key).is_some()
+    }
+
+    pub fn append(&mut self, key: &str, val: &str) {
+        self.entries.push((String::from(key), String::from(val)));
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.entries.iter().map(|x| (x.0.as_str(), x.1.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    //Inverse of parse, every key and value is percent encoded
+    pub fn encode(&self) -> String {
+        self.entries
+            .iter()
+            .map(|(key, val)| format!("{}={}", percent_encode(key), percent_encode(val)))
+            .collect::<Vec<String>>()
+            .join("&")
+    }
+}
+
+//Types that can be built from query or form pairs, the error is shown to the client
+pub trait FromUrlEncoded: Sized {
+    fn from_url_encoded(params: UrlEncodedParams) -> Result<Self, String>;
+}
+
+impl FromUrlEncoded for UrlEncodedParams {
+    fn from_url_encoded(params: UrlEncodedParams) -> Result<Self, String> {
+        Ok(params)
+    }
+}
+
+//Pairs are decoded first so limits and charsets are handled the same for typed and untyped forms
+#[cfg(feature = "serde")]
+impl<T: serde::de::DeserializeOwned> FromUrlEncoded for T {
+    fn from_url_encoded(params: UrlEncodedParams) -> Result<Self, String> {
+        serde_urlencoded::from_str(&params.encode()).map_err(|e| e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::http::url_encoding::{percent_decode, percent_encode, UrlEncodedParams};
+
+    #[test]
+    fn it_decodes_percent_escapes() {
+        assert_eq!(percent_decode("my%20file.txt").unwrap(), "my file.txt");
+        assert_eq!(percent_decode("ol%C3%A1+x").unwrap(), "olá+x");
+        assert_eq!(percent_decode("%2e%2E").unwrap(), "..");
+    }
+
+    #[test]
+    fn it_rejects_broken_escapes() {
+        assert!(percent_decode("bad%2").is_none());
+        assert!(percent_decode("bad%zz").is_none());
+        assert!(percent_decode("%ff").is_none());
+    }
+
+    #[test]
+    fn it_encodes_reserved_characters() {
+        assert_eq!(percent_encode("a b/c?d=é"), "a%20b%2Fc%3Fd%3D%C3%A9");
+    }
+
+    #[test]
+    fn it_parses_form_pairs() {
+        let params = UrlEncodedParams::parse("x=1&name=Nuno+P&x=2&flag&empty=&&bad=%zz");
+        let xs: Vec<&str> = params.get_all("x").collect();
+        assert_eq!(xs, vec!["1", "2"]);
+        assert_eq!(params.get("name"), Some("Nuno P"));
+        assert_eq!(params.get("flag"), Some(""));
+        assert_eq!(params.get("empty"), Some(""));
+        assert_eq!(params.get("bad"), Some("%zz"));
+        assert_eq!(params.len(), 6);
+    }
+
+    #[test]
+    fn it_encodes_pairs_back() {
+        let params = UrlEncodedParams::parse("name=Nuno+P&tag=a%26b&tag=");
+        assert_eq!(params.encode(), "name=Nuno%20P&tag=a%26b&tag=");
+        assert_eq!(UrlEncodedParams::parse(&params.encode()), params);
+    }
+}
@@ -1,19 +1,18 @@
 use web_server::http::http_server::HttpServer;
+use web_server::http::middleware::RequestLogger;
 use web_server::http::HttpResponse;
 
 fn main() {
     let mut server = HttpServer::new("127.0.0.1", 7878, 32);
 
-    server.get("/ola", |_| {
-        HttpResponse::default().with_string_content("Olá Malin!")
-    });
+    server.middleware(RequestLogger);
 
-    server.get("/", |_| {
-        HttpResponse::default().with_string_content("Welcome!")
-    });
+    server.get("/ola", |_| "Olá Malin!");
+
+    server.get("/", |_| "Welcome!");
 
-    server.get("/test/?param", |x| {
-        let param = x.route_params.get("param").unwrap();
+    server.get("/test/{param}", |x| {
+        let param: String = x.param("param").unwrap();
         let content = format!("Called with param: {}\n", param);
         let header_val = String::from("my val");
         HttpResponse::default()
@@ -1,8 +1,15 @@
-use reqwest;
+use crossbeam::channel::unbounded;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::sync::atomic::AtomicUsize;
 use std::sync::atomic::Ordering::Relaxed;
+use std::sync::Arc;
 use std::{thread, time};
+use web_server::http::extract::{extract, Cookies, Form, Path};
 use web_server::http::http_server::HttpServer;
-use web_server::http::HttpResponse;
+use web_server::http::middleware::{with_middleware, DefaultHeaders, Middleware, Next};
+use web_server::http::multipart::MultipartConfig;
+use web_server::http::{HttpContentType, HttpError, HttpRequest, HttpResponse};
 
 #[test]
 fn simple_path_found() {
@@ -35,3 +42,632 @@ fn file_served() {
     assert_eq!(resp, "Test content here!\n");
     serve_should_turn_off.store(true, Relaxed);
 }
+
+fn read_response(reader: &mut BufReader<&TcpStream>) -> (String, Vec<String>, String) {
+    let mut status_line = String::new();
+    reader.read_line(&mut status_line).unwrap();
+    let mut headers = Vec::new();
+    let mut content_length = 0;
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        if line == "\r\n" {
+            break;
+        }
+        let line = String::from(line.trim_end());
+        if let Some(length) = line.strip_prefix("Content-Length: ") {
+            content_length = length.parse().unwrap();
+        }
+        headers.push(line);
+    }
+    let mut content = vec![0; content_length];
+    reader.read_exact(&mut content).unwrap();
+    (
+        String::from(status_line.trim_end()),
+        headers,
+        String::from_utf8(content).unwrap(),
+    )
+}
+
+#[test]
+fn connection_kept_alive_between_requests() {
+    let mut server = HttpServer::new("127.0.0.1", 7880, 1);
+    let serve_should_turn_off = server.should_turn_off.clone();
+    server.get("/path", |_| {
+        HttpResponse::default().with_string_content("Found!")
+    });
+    thread::spawn(|| server.listen());
+    thread::sleep(time::Duration::from_millis(100));
+
+    let mut stream = TcpStream::connect("127.0.0.1:7880").unwrap();
+    let mut reader = BufReader::new(&stream);
+    for _ in 0..3 {
+        (&stream)
+            .write_all(b"GET /path HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .unwrap();
+        let (status, headers, content) = read_response(&mut reader);
+        assert_eq!(status, "HTTP/1.1 200 OK");
+        assert!(!headers.contains(&String::from("Connection: close")));
+        assert_eq!(content, "Found!");
+    }
+    (&stream)
+        .write_all(b"GET /path HTTP/1.1\r\nConnection: close\r\n\r\n")
+        .unwrap();
+    let (_, headers, _) = read_response(&mut reader);
+    assert!(headers.contains(&String::from("Connection: close")));
+    let mut rest = Vec::new();
+    stream.read_to_end(&mut rest).unwrap();
+    assert!(rest.is_empty());
+    serve_should_turn_off.store(true, Relaxed);
+}
+
+#[test]
+fn pipelined_requests_answered_in_order() {
+    let mut server = HttpServer::new("127.0.0.1", 7881, 1);
+    let serve_should_turn_off = server.should_turn_off.clone();
+    server.get("/test/?param", |x| {
+        HttpResponse::default().with_string_content(x.route_params.get("param").unwrap())
+    });
+    server.post("/echo", |x| {
+        HttpResponse::default()
+            .with_string_content(String::from_utf8(x.content.unwrap()).unwrap().as_str())
+    });
+    thread::spawn(|| server.listen());
+    thread::sleep(time::Duration::from_millis(100));
+
+    let stream = TcpStream::connect("127.0.0.1:7881").unwrap();
+    (&stream)
+        .write_all(
+            b"GET /test/first HTTP/1.1\r\n\r\n\
+              POST /echo HTTP/1.1\r\nContent-Length: 4\r\n\r\nbody\
+              GET /test/last HTTP/1.1\r\n\r\n",
+        )
+        .unwrap();
+    let mut reader = BufReader::new(&stream);
+    assert_eq!(read_response(&mut reader).2, "first");
+    assert_eq!(read_response(&mut reader).2, "body");
+    assert_eq!(read_response(&mut reader).2, "last");
+    serve_should_turn_off.store(true, Relaxed);
+}
+
+#[test]
+fn connection_closed_after_max_requests() {
+    let mut server = HttpServer::new("127.0.0.1", 7882, 1);
+    let serve_should_turn_off = server.should_turn_off.clone();
+    server.set_max_requests_per_connection(2);
+    server.get("/path", |_| {
+        HttpResponse::default().with_string_content("Found!")
+    });
+    thread::spawn(|| server.listen());
+    thread::sleep(time::Duration::from_millis(100));
+
+    let stream = TcpStream::connect("127.0.0.1:7882").unwrap();
+    (&stream)
+        .write_all(b"GET /path HTTP/1.1\r\n\r\nGET /path HTTP/1.1\r\n\r\n")
+        .unwrap();
+    let mut reader = BufReader::new(&stream);
+    let (_, headers, _) = read_response(&mut reader);
+    assert!(!headers.contains(&String::from("Connection: close")));
+    let (_, headers, _) = read_response(&mut reader);
+    assert!(headers.contains(&String::from("Connection: close")));
+    serve_should_turn_off.store(true, Relaxed);
+}
+
+#[test]
+fn idle_connection_closed_after_timeout() {
+    let mut server = HttpServer::new("127.0.0.1", 7883, 1);
+    let serve_should_turn_off = server.should_turn_off.clone();
+    server.set_keep_alive_timeout(time::Duration::from_millis(100));
+    thread::spawn(|| server.listen());
+    thread::sleep(time::Duration::from_millis(100));
+
+    let mut stream = TcpStream::connect("127.0.0.1:7883").unwrap();
+    stream
+        .set_read_timeout(Some(time::Duration::from_secs(5)))
+        .unwrap();
+    let mut rest = Vec::new();
+    stream.read_to_end(&mut rest).unwrap();
+    assert!(rest.is_empty());
+    serve_should_turn_off.store(true, Relaxed);
+}
+
+#[test]
+fn invalid_request_answered_with_error_status() {
+    let server = HttpServer::new("127.0.0.1", 7884, 1);
+    let serve_should_turn_off = server.should_turn_off.clone();
+    thread::spawn(|| server.listen());
+    thread::sleep(time::Duration::from_millis(100));
+
+    let requests: [(&[u8], &str); 3] = [
+        (
+            b"GET / HTTP/1.1\r\nTransfer-Encoding: gzip\r\n\r\n",
+            "HTTP/1.1 501 Not Implemented",
+        ),
+        (
+            b"GET / HTTP/3\r\n\r\n",
+            "HTTP/1.1 505 HTTP Version Not Supported",
+        ),
+        (
+            b"GET / HTTP/1.1\r\nbroken header\r\n\r\n",
+            "HTTP/1.1 400 Bad Request",
+        ),
+    ];
+    //The same worker thread must survive every bad request
+    for (request, expected_status) in requests.iter() {
+        let stream = TcpStream::connect("127.0.0.1:7884").unwrap();
+        (&stream).write_all(request).unwrap();
+        let mut reader = BufReader::new(&stream);
+        let (status, headers, _) = read_response(&mut reader);
+        assert_eq!(&status, expected_status);
+        assert!(headers.contains(&String::from("Connection: close")));
+    }
+    serve_should_turn_off.store(true, Relaxed);
+}
+
+#[test]
+fn streamed_response_sent_chunked() {
+    let mut server = HttpServer::new("127.0.0.1", 7885, 1);
+    let serve_should_turn_off = server.should_turn_off.clone();
+    server.get("/stream", |_| {
+        let (s, r) = unbounded();
+        thread::spawn(move || {
+            for i in 0..3 {
+                s.send(format!("part {}\n", i).into_bytes()).unwrap();
+            }
+        });
+        HttpResponse::default()
+            .with_channel_content(r, HttpContentType::TEXT_PLAIN)
+            .with_trailer(String::from("Checksum"), String::from("none"))
+    });
+    thread::spawn(|| server.listen());
+    thread::sleep(time::Duration::from_millis(100));
+
+    let resp = reqwest::blocking::get("http://localhost:7885/stream")
+        .unwrap()
+        .text()
+        .unwrap();
+    assert_eq!(resp, "part 0\npart 1\npart 2\n");
+
+    let mut stream = TcpStream::connect("127.0.0.1:7885").unwrap();
+    stream
+        .write_all(b"GET /stream HTTP/1.1\r\nTE: trailers\r\nConnection: close\r\n\r\n")
+        .unwrap();
+    let mut raw = String::new();
+    stream.read_to_string(&mut raw).unwrap();
+    assert!(raw.contains("Transfer-Encoding: chunked\r\n"));
+    assert!(raw.contains("Trailer: Checksum\r\n"));
+    assert!(raw.ends_with("\r\n7\r\npart 2\n\r\n0\r\nChecksum: none\r\n\r\n"));
+    serve_should_turn_off.store(true, Relaxed);
+}
+
+#[test]
+fn head_answered_without_body() {
+    let mut server = HttpServer::new("127.0.0.1", 7886, 1);
+    let serve_should_turn_off = server.should_turn_off.clone();
+    server.get("/path", |_| {
+        HttpResponse::default().with_string_content("Found!")
+    });
+    thread::spawn(|| server.listen());
+    thread::sleep(time::Duration::from_millis(100));
+
+    let stream = TcpStream::connect("127.0.0.1:7886").unwrap();
+    (&stream)
+        .write_all(b"HEAD /path HTTP/1.1\r\n\r\nGET /path HTTP/1.1\r\n\r\n")
+        .unwrap();
+    let mut reader = BufReader::new(&stream);
+    let mut head_response = Vec::new();
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        if line == "\r\n" {
+            break;
+        }
+        head_response.push(String::from(line.trim_end()));
+    }
+    assert_eq!(head_response[0], "HTTP/1.1 200 OK");
+    assert!(head_response.contains(&String::from("Content-Length: 6")));
+    //The next bytes must already be the response to the GET
+    let (status, _, content) = read_response(&mut reader);
+    assert_eq!(status, "HTTP/1.1 200 OK");
+    assert_eq!(content, "Found!");
+    serve_should_turn_off.store(true, Relaxed);
+}
+
+#[test]
+fn no_content_sent_without_body() {
+    let mut server = HttpServer::new("127.0.0.1", 7887, 1);
+    let serve_should_turn_off = server.should_turn_off.clone();
+    server.delete("/item", |_| {
+        HttpResponse::default()
+            .with_string_content("ignored")
+            .no_content()
+    });
+    server.get("/old", |_| {
+        HttpResponse::default().moved_permanently("/new")
+    });
+    thread::spawn(|| server.listen());
+    thread::sleep(time::Duration::from_millis(100));
+
+    let stream = TcpStream::connect("127.0.0.1:7887").unwrap();
+    (&stream)
+        .write_all(b"DELETE /item HTTP/1.1\r\n\r\nGET /old HTTP/1.1\r\n\r\n")
+        .unwrap();
+    let mut reader = BufReader::new(&stream);
+    let (status, headers, content) = read_response(&mut reader);
+    assert_eq!(status, "HTTP/1.1 204 No Content");
+    assert!(!headers.iter().any(|x| x.starts_with("Content-Length")));
+    assert_eq!(content, "");
+    let (status, headers, _) = read_response(&mut reader);
+    assert_eq!(status, "HTTP/1.1 301 Moved Permanently");
+    assert!(headers.contains(&String::from("Location: /new")));
+    serve_should_turn_off.store(true, Relaxed);
+}
+
+#[test]
+fn query_string_not_part_of_path() {
+    let mut server = HttpServer::new("127.0.0.1", 7888, 1);
+    let serve_should_turn_off = server.should_turn_off.clone();
+    server.serve_files("static", "static");
+    server.get("/search", |x| {
+        let terms: Vec<&str> = x.query_params.get_all("term").collect();
+        HttpResponse::default().with_string_content(&terms.join(","))
+    });
+    thread::spawn(|| server.listen());
+    thread::sleep(time::Duration::from_millis(100));
+    //A single client reuses the connection, the only worker would be busy with the first one otherwise
+    let client = reqwest::blocking::Client::new();
+    let resp = client
+        .get("http://localhost:7888/static/test_content.txt?version=2")
+        .send()
+        .unwrap()
+        .text()
+        .unwrap();
+    assert_eq!(resp, "Test content here!\n");
+    let resp = client
+        .get("http://localhost:7888/search?term=a%20b&term=c")
+        .send()
+        .unwrap()
+        .text()
+        .unwrap();
+    assert_eq!(resp, "a b,c");
+    serve_should_turn_off.store(true, Relaxed);
+}
+
+#[test]
+fn path_traversal_refused() {
+    let mut server = HttpServer::new("127.0.0.1", 7889, 1);
+    let serve_should_turn_off = server.should_turn_off.clone();
+    server.serve_files("static", "static");
+    thread::spawn(|| server.listen());
+    thread::sleep(time::Duration::from_millis(100));
+
+    let stream = TcpStream::connect("127.0.0.1:7889").unwrap();
+    (&stream)
+        .write_all(b"GET /static/../Cargo.toml HTTP/1.1\r\n\r\nGET /static/%2e%2e/%2e%2e/etc/passwd HTTP/1.1\r\n\r\n")
+        .unwrap();
+    let mut reader = BufReader::new(&stream);
+    assert_eq!(read_response(&mut reader).0, "HTTP/1.1 403 Forbidden");
+    assert_eq!(read_response(&mut reader).0, "HTTP/1.1 403 Forbidden");
+    serve_should_turn_off.store(true, Relaxed);
+}
+
+#[test]
+fn middlewares_run_around_handlers() {
+    let mut server = HttpServer::new("127.0.0.1", 7890, 1);
+    let serve_should_turn_off = server.should_turn_off.clone();
+    server.middleware(DefaultHeaders::new().with_header("X-Served-By", "web_server"));
+    server.middleware_for("/admin", |request: HttpRequest, next: &Next| match request
+        .headers
+        .get("Authorization")
+    {
+        Some("secret") => next.run(request),
+        _ => HttpResponse::default().unauthorized(),
+    });
+    server.get("/admin/panel", |_| {
+        HttpResponse::default().with_string_content("Panel")
+    });
+    let tag: Arc<dyn Middleware> = Arc::new(|request, next: &Next| {
+        next.run(request)
+            .with_header(String::from("X-Route"), String::from("tagged"))
+    });
+    server.get(
+        "/tagged",
+        with_middleware(vec![tag], |_| HttpResponse::default()),
+    );
+    thread::spawn(|| server.listen());
+    thread::sleep(time::Duration::from_millis(100));
+
+    let client = reqwest::blocking::Client::new();
+    let resp = client
+        .get("http://localhost:7890/admin/panel")
+        .send()
+        .unwrap();
+    assert_eq!(resp.status().as_u16(), 401);
+    assert_eq!(resp.headers()["X-Served-By"], "web_server");
+    let resp = client
+        .get("http://localhost:7890/admin/panel")
+        .header("Authorization", "secret")
+        .send()
+        .unwrap();
+    assert_eq!(resp.text().unwrap(), "Panel");
+    let resp = client.get("http://localhost:7890/tagged").send().unwrap();
+    assert_eq!(resp.headers()["X-Route"], "tagged");
+    serve_should_turn_off.store(true, Relaxed);
+}
+
+#[test]
+fn handler_errors_rendered_by_error_handler() {
+    let mut server = HttpServer::new("127.0.0.1", 7891, 1);
+    let serve_should_turn_off = server.should_turn_off.clone();
+    server.get("/items/{id}", |x| -> Result<HttpResponse, HttpError> {
+        let id: u32 = x.param("id")?;
+        Err(HttpError::not_found(&format!("No item {}", id)))
+    });
+    server.on_error(|e| e.to_problem_json());
+    thread::spawn(|| server.listen());
+    thread::sleep(time::Duration::from_millis(100));
+
+    let resp = reqwest::blocking::get("http://localhost:7891/items/7").unwrap();
+    assert_eq!(resp.status(), 404);
+    assert_eq!(
+        resp.headers()["content-type"].to_str().unwrap(),
+        "application/problem+json"
+    );
+    assert_eq!(
+        resp.text().unwrap(),
+        "{\"type\":\"about:blank\",\"title\":\"Not Found\",\"status\":404,\"detail\":\"No item 7\"}"
+    );
+    serve_should_turn_off.store(true, Relaxed);
+}
+
+#[test]
+fn extractors_build_handler_args() {
+    let mut server = HttpServer::new("127.0.0.1", 7892, 1);
+    let serve_should_turn_off = server.should_turn_off.clone();
+    server.post(
+        "/users/{id:u32}",
+        extract(|Path(id): Path<u32>, Form(form): Form, cookies: Cookies| {
+            let name = form.get("name").unwrap_or("nobody");
+            let session = cookies.get("session").unwrap_or("-");
+            format!("{} {} {}", id, name, session)
+        }),
+    );
+    thread::spawn(|| server.listen());
+    thread::sleep(time::Duration::from_millis(100));
+
+    let client = reqwest::blocking::Client::new();
+    let resp = client
+        .post("http://localhost:7892/users/3")
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .header("Cookie", "session=xyz")
+        .body("name=Ana")
+        .send()
+        .unwrap();
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.text().unwrap(), "3 Ana xyz");
+
+    let resp = client
+        .post("http://localhost:7892/users/3")
+        .header("Content-Type", "text/plain")
+        .body("name=Ana")
+        .send()
+        .unwrap();
+    assert_eq!(resp.status(), 415);
+    serve_should_turn_off.store(true, Relaxed);
+}
+
+#[test]
+fn state_and_extensions_reach_handlers() {
+    #[derive(Clone)]
+    struct User(String);
+
+    let mut server = HttpServer::new("127.0.0.1", 7893, 1);
+    let serve_should_turn_off = server.should_turn_off.clone();
+    server.with_state(AtomicUsize::new(0));
+    server.middleware(|mut request: HttpRequest, next: &Next| {
+        if let Some(token) = request.headers.get("Authorization") {
+            let user = User(String::from(token));
+            request.extensions.insert(user);
+        }
+        next.run(request)
+    });
+    server.get("/visits", |x| {
+        let visits = x.state::<AtomicUsize>().unwrap().fetch_add(1, Relaxed) + 1;
+        match x.extensions.get::<User>() {
+            Some(user) => format!("{} {}", user.0, visits),
+            None => format!("anonymous {}", visits),
+        }
+    });
+    thread::spawn(|| server.listen());
+    thread::sleep(time::Duration::from_millis(100));
+
+    let client = reqwest::blocking::Client::new();
+    let resp = client.get("http://localhost:7893/visits").send().unwrap();
+    assert_eq!(resp.text().unwrap(), "anonymous 1");
+    let resp = client
+        .get("http://localhost:7893/visits")
+        .header("Authorization", "ana")
+        .send()
+        .unwrap();
+    assert_eq!(resp.text().unwrap(), "ana 2");
+    serve_should_turn_off.store(true, Relaxed);
+}
+
+#[test]
+fn connection_info_available_to_handlers() {
+    let mut server = HttpServer::new("127.0.0.1", 7894, 1);
+    let serve_should_turn_off = server.should_turn_off.clone();
+    server.get("/whoami", |x| {
+        let connection = x.connection.unwrap();
+        format!(
+            "{} {} {}",
+            connection.peer_addr == x.peer_addr().unwrap(),
+            connection.listener_addr,
+            connection.request_number
+        )
+    });
+    thread::spawn(|| server.listen());
+    thread::sleep(time::Duration::from_millis(100));
+
+    let stream = TcpStream::connect("127.0.0.1:7894").unwrap();
+    let mut reader = BufReader::new(&stream);
+    for request_number in 1..3 {
+        (&stream)
+            .write_all(b"GET /whoami HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .unwrap();
+        let (_, _, content) = read_response(&mut reader);
+        assert_eq!(content, format!("true 127.0.0.1:7894 {}", request_number));
+    }
+    serve_should_turn_off.store(true, Relaxed);
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn json_bodies_parsed_and_sent() {
+    use serde::{Deserialize, Serialize};
+    use web_server::http::Json;
+
+    #[derive(Serialize, Deserialize)]
+    struct Item {
+        name: String,
+        quantity: u32,
+    }
+
+    let mut server = HttpServer::new("127.0.0.1", 7895, 1);
+    let serve_should_turn_off = server.should_turn_off.clone();
+    server.set_json_limit(64);
+    server.post("/items", |x| -> Result<Json<Item>, HttpError> {
+        let mut item: Item = x.json()?;
+        item.quantity *= 2;
+        Ok(Json(item))
+    });
+    thread::spawn(|| server.listen());
+    thread::sleep(time::Duration::from_millis(100));
+
+    let client = reqwest::blocking::Client::new();
+    let resp = client
+        .post("http://localhost:7895/items")
+        .header("Content-Type", "application/json")
+        .body("{\"name\":\"apple\",\"quantity\":2}")
+        .send()
+        .unwrap();
+    assert_eq!(resp.status(), 200);
+    assert_eq!(
+        resp.headers()["content-type"].to_str().unwrap(),
+        "application/json"
+    );
+    assert_eq!(resp.text().unwrap(), "{\"name\":\"apple\",\"quantity\":4}");
+
+    let resp = client
+        .post("http://localhost:7895/items")
+        .header("Content-Type", "application/json")
+        .body("{\"name\":\"apple\"}")
+        .send()
+        .unwrap();
+    assert_eq!(resp.status(), 422);
+
+    let resp = client
+        .post("http://localhost:7895/items")
+        .header("Content-Type", "application/json")
+        .body(format!(
+            "{{\"name\":\"{}\",\"quantity\":2}}",
+            "a".repeat(100)
+        ))
+        .send()
+        .unwrap();
+    assert_eq!(resp.status(), 413);
+
+    //Refused from the headers alone, the server doesn't wait for a body bigger than the limit
+    let mut stream = TcpStream::connect("127.0.0.1:7895").unwrap();
+    stream
+        .set_read_timeout(Some(time::Duration::from_secs(2)))
+        .unwrap();
+    stream
+        .write_all(b"POST /items HTTP/1.1\r\nHost: localhost\r\nContent-Type: application/json\r\nContent-Length: 1000000\r\n\r\n{")
+        .unwrap();
+    let mut reader = BufReader::new(&stream);
+    let (status_line, _, _) = read_response(&mut reader);
+    assert_eq!(status_line, "HTTP/1.1 413 Content Too Large");
+    serve_should_turn_off.store(true, Relaxed);
+}
+
+#[test]
+fn multipart_uploads_parsed() {
+    let mut server = HttpServer::new("127.0.0.1", 7896, 1);
+    let serve_should_turn_off = server.should_turn_off.clone();
+    server.set_multipart_config(MultipartConfig {
+        max_part_size: 100,
+        max_total_size: 1024,
+        spool_threshold: 4,
+        ..MultipartConfig::default()
+    });
+    server.post("/upload", |x| -> Result<String, HttpError> {
+        let mut summary = Vec::new();
+        for part in x.multipart()? {
+            let part = part?;
+            let name = part.file_name().or_else(|| part.name()).unwrap_or_default();
+            summary.push(format!("{}={}", name, part.size()));
+        }
+        Ok(summary.join(","))
+    });
+    thread::spawn(|| server.listen());
+    thread::sleep(time::Duration::from_millis(100));
+
+    let client = reqwest::blocking::Client::new();
+    let upload = |content: &str| {
+        let body = format!(
+            "--b0undary\r\nContent-Disposition: form-data; name=\"kind\"\r\n\r\nphoto\r\n\
+             --b0undary\r\nContent-Disposition: form-data; name=\"file\"; filename=\"cat.png\"\r\n\
+             Content-Type: image/png\r\n\r\n{}\r\n--b0undary--\r\n",
+            content
+        );
+        client
+            .post("http://localhost:7896/upload")
+            .header("Content-Type", "multipart/form-data; boundary=b0undary")
+            .body(body)
+            .send()
+            .unwrap()
+    };
+    let resp = upload("0123456789");
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.text().unwrap(), "kind=5,cat.png=10");
+
+    let resp = upload(&"x".repeat(200));
+    assert_eq!(resp.status(), 413);
+    //The rest of the refused body was skipped, so the same connection keeps working
+    let resp = upload("abc");
+    assert_eq!(resp.text().unwrap(), "kind=5,cat.png=3");
+    let resp = upload(&"x".repeat(2000));
+    assert_eq!(resp.status(), 413);
+    serve_should_turn_off.store(true, Relaxed);
+}
+
+#[test]
+fn worker_survives_panicking_handler() {
+    let mut server = HttpServer::new("127.0.0.1", 7897, 1);
+    let serve_should_turn_off = server.should_turn_off.clone();
+    server.get("/panic", |_| -> HttpResponse { panic!("handler bug") });
+    server.get("/ok", |_| {
+        HttpResponse::default().with_string_content("Still here")
+    });
+    thread::spawn(|| server.listen());
+    thread::sleep(time::Duration::from_millis(100));
+
+    let stream = TcpStream::connect("127.0.0.1:7897").unwrap();
+    (&stream).write_all(b"GET /panic HTTP/1.1\r\n\r\n").unwrap();
+    let mut reader = BufReader::new(&stream);
+    let (status, headers, _) = read_response(&mut reader);
+    assert_eq!(status, "HTTP/1.1 500 Internal Server Error");
+    assert!(headers.contains(&String::from("Connection: close")));
+    let mut rest = Vec::new();
+    reader.read_to_end(&mut rest).unwrap();
+    assert!(rest.is_empty());
+
+    //The only worker thread has to answer the next connection
+    let resp = reqwest::blocking::get("http://localhost:7897/ok")
+        .unwrap()
+        .text()
+        .unwrap();
+    assert_eq!(resp, "Still here");
+    serve_should_turn_off.store(true, Relaxed);
+}